@@ -0,0 +1,96 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use vec_collections::radix_tree::{
+    AbstractRadixTree, AbstractRadixTreeMut, ArtRadixTree, RadixTree,
+};
+
+/// random keys, so there is a high fan out near the root
+fn random_keys(n: usize, rand: &mut impl Rng) -> Vec<Vec<u8>> {
+    (0..n)
+        .map(|_| {
+            let len = rand.gen_range(4, 16);
+            (0..len).map(|_| rand.gen::<u8>()).collect()
+        })
+        .collect()
+}
+
+fn contains<T: AbstractRadixTree<u8, ()>>(tree: &T, keys: &[Vec<u8>]) -> usize {
+    keys.iter().filter(|key| tree.contains_key(key)).count()
+}
+
+fn creation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("RadixTree creation");
+    let mut rand = rand::rngs::StdRng::from_seed([0u8; 32]);
+    for n in [1000, 10000, 100000].iter().cloned() {
+        let keys = random_keys(n, &mut rand);
+        group.bench_with_input(BenchmarkId::new("RadixTree", n), &keys, |b, keys| {
+            b.iter(|| {
+                let tree: RadixTree<u8, ()> = black_box(keys).iter().map(|k| (k, ())).collect();
+                tree
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("ArtRadixTree", n), &keys, |b, keys| {
+            b.iter(|| {
+                let tree: ArtRadixTree<()> = black_box(keys).iter().map(|k| (k, ())).collect();
+                tree
+            })
+        });
+    }
+}
+
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("RadixTree lookup 1000");
+    let mut rand = rand::rngs::StdRng::from_seed([0u8; 32]);
+    for n in [1000, 10000, 100000].iter().cloned() {
+        let keys = random_keys(n, &mut rand);
+        // half hits, half misses
+        let mut lookup = keys.iter().take(500).cloned().collect::<Vec<_>>();
+        lookup.extend(random_keys(500, &mut rand));
+        lookup.shuffle(&mut rand);
+        let flat: RadixTree<u8, ()> = keys.iter().map(|k| (k, ())).collect();
+        let art: ArtRadixTree<()> = keys.iter().map(|k| (k, ())).collect();
+        group.bench_with_input(BenchmarkId::new("RadixTree", n), &lookup, |b, lookup| {
+            b.iter(|| contains(black_box(&flat), lookup))
+        });
+        group.bench_with_input(BenchmarkId::new("ArtRadixTree", n), &lookup, |b, lookup| {
+            b.iter(|| contains(black_box(&art), lookup))
+        });
+    }
+}
+
+fn union(c: &mut Criterion) {
+    let mut group = c.benchmark_group("RadixTree union");
+    let mut rand = rand::rngs::StdRng::from_seed([0u8; 32]);
+    for n in [1000, 10000, 100000].iter().cloned() {
+        let a = random_keys(n, &mut rand);
+        let b = random_keys(n, &mut rand);
+        let flat: (RadixTree<u8, ()>, RadixTree<u8, ()>) = (
+            a.iter().map(|k| (k, ())).collect(),
+            b.iter().map(|k| (k, ())).collect(),
+        );
+        let art: (ArtRadixTree<()>, ArtRadixTree<()>) = (
+            a.iter().map(|k| (k, ())).collect(),
+            b.iter().map(|k| (k, ())).collect(),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("RadixTree", n),
+            &flat,
+            |bencher, (a, b)| bencher.iter(|| black_box(a).union(black_box(b))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("ArtRadixTree", n),
+            &art,
+            |bencher, (a, b)| bencher.iter(|| black_box(a).union(black_box(b))),
+        );
+        #[cfg(feature = "rayon")]
+        group.bench_with_input(
+            BenchmarkId::new("RadixTree par_union", n),
+            &flat,
+            |bencher, (a, b)| bencher.iter(|| black_box(a).par_union(black_box(b))),
+        );
+    }
+}
+
+criterion_group!(benches, creation, lookup, union);
+criterion_main!(benches);
@@ -26,7 +26,7 @@ impl<'a, K, V> Iterator for AbstractBTreeMapIter<'a, K, V> {
 }
 
 impl<K, V> AbstractBTreeMap<K, V> for BTreeMap<K, V> {
-    fn iter(&self) -> AbstractBTreeMapIter<K, V> {
+    fn iter(&self) -> AbstractBTreeMapIter<'_, K, V> {
         AbstractBTreeMapIter::BTreeMap(self.iter())
     }
 
@@ -40,7 +40,7 @@ impl<K, V> AbstractBTreeMap<K, V> for BTreeMap<K, V> {
 }
 
 impl<K, V> AbstractBTreeMap<K, V> for ArchivedBTreeMap<K, V> {
-    fn iter(&self) -> AbstractBTreeMapIter<K, V> {
+    fn iter(&self) -> AbstractBTreeMapIter<'_, K, V> {
         AbstractBTreeMapIter::ArchivedBTreeMap(self.iter())
     }
 
@@ -54,7 +54,7 @@ impl<K, V> AbstractBTreeMap<K, V> for ArchivedBTreeMap<K, V> {
 }
 
 pub trait AbstractBTreeMap<K, V> {
-    fn iter(&self) -> AbstractBTreeMapIter<K, V>;
+    fn iter(&self) -> AbstractBTreeMapIter<'_, K, V>;
     fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Ord + Borrow<Q>,
@@ -23,6 +23,14 @@ impl<I: Iterator> Iterator for VecSetIter<I> {
     }
 }
 
+impl<I: DoubleEndedIterator> DoubleEndedIterator for VecSetIter<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.i.next_back()
+    }
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for VecSetIter<I> {}
+
 /// An interator that is guaranteed to be sorted by key
 pub struct VecMapIter<I> {
     i: I,
@@ -48,6 +56,14 @@ impl<I: Iterator> Iterator for VecMapIter<I> {
     }
 }
 
+impl<I: DoubleEndedIterator> DoubleEndedIterator for VecMapIter<I> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.i.next_back()
+    }
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for VecMapIter<I> {}
+
 pub(crate) struct SliceIterator<'a, T>(pub &'a [T]);
 
 impl<'a, T> Iterator for SliceIterator<'a, T> {
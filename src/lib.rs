@@ -80,6 +80,9 @@ mod vec_set;
 #[cfg(feature = "radixtree")]
 pub mod radix_tree;
 
+#[cfg(feature = "radix_db")]
+pub mod radix_db;
+
 #[cfg(feature = "total")]
 pub mod total_vec_map;
 
@@ -94,7 +97,8 @@ mod iterators;
 
 mod macros;
 
-pub use dedup::{sort_dedup, sort_dedup_by_key};
+pub use dedup::{sort_dedup, sort_dedup_by_key, Keep};
+#[allow(unused_imports)]
 pub use macros::*;
 pub use smallvec::Array;
 pub use vec_map::*;
@@ -0,0 +1,1196 @@
+//! A persistent, append only key value store based on [ArcRadixTree]
+//!
+//! The database keeps the current state as an [ArcRadixTree] in memory. On [flush](RadixDb::flush), the
+//! tree is serialized using rkyv and appended to a single file. Subtrees that are shared with the last flushed
+//! state are not written again, but referenced, so the size of a flush is proportional to the size of the change.
+//!
+//! Since the file grows with every flush, it is necessary to occasionally [vacuum](RadixDb::vacuum) the db,
+//! which writes just the current state to a new file and atomically replaces the old one.
+//!
+//! Each flush is written as a record with a small header that contains the length and a checksum of the record.
+//! If a flush was interrupted, e.g. by a crash, the damaged trailing record is discarded on load.
+//! All data is validated on load, so a corrupted file results in an error, not in undefined behaviour.
+use std::{
+    alloc::{Layout, LayoutError},
+    any::TypeId,
+    collections::{hash_map, BTreeMap},
+    convert::TryInto,
+    fmt, fs, io,
+    io::{Read, Write},
+    ops::Range,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use bytecheck::CheckBytes;
+use futures::{
+    channel::mpsc::{UnboundedReceiver, UnboundedSender},
+    future,
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use parking_lot::Mutex;
+use rkyv::{
+    de::{deserializers::SharedDeserializeMapError, SharedDeserializeRegistry, SharedPointer},
+    ser::{
+        serializers::{
+            AllocScratch, CompositeSerializer, FallbackScratch, HeapScratch,
+            SharedSerializeMapError, WriteSerializer,
+        },
+        Serializer, SharedSerializeRegistry,
+    },
+    validation::{
+        check_archived_value_with_context,
+        validators::{ArchiveError, DefaultValidator, DefaultValidatorError},
+        ArchiveContext, SharedContext,
+    },
+    AlignedVec, Archive, Archived, Deserialize, Fallible, Serialize,
+};
+
+use crate::radix_tree::{self, AbstractRadixTreeMut, ArcRadixTree, Change};
+
+/// Trait for everything that is needed for a key component of a radix db
+pub trait TKey: radix_tree::TKey + Archive<Archived = Self> {}
+
+impl<T: radix_tree::TKey + Archive<Archived = T>> TKey for T {}
+
+/// Trait for everything that is needed for a value of a radix db
+pub trait TValue: radix_tree::TValue + Archive {}
+
+impl<T: radix_tree::TValue + Archive> TValue for T {}
+
+/// Error type for radix db operations
+#[derive(Debug)]
+pub enum RadixDbError {
+    /// error from the underlying storage
+    Io(io::Error),
+    /// error while serializing the tree
+    Serialize(String),
+    /// error while deserializing the tree
+    Deserialize(String),
+    /// the stored data failed validation
+    Validation(String),
+}
+
+impl fmt::Display for RadixDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::Serialize(e) => write!(f, "error while serializing: {}", e),
+            Self::Deserialize(e) => write!(f, "error while deserializing: {}", e),
+            Self::Validation(e) => write!(f, "error while validating: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RadixDbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RadixDbError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Result type for radix db operations
+pub type Result<T> = std::result::Result<T, RadixDbError>;
+
+/// The state of a tree before and after a flush
+#[derive(Clone)]
+pub struct Batch<K: TKey, V: TValue> {
+    v0: ArcRadixTree<K, V>,
+    v1: ArcRadixTree<K, V>,
+}
+
+impl<K: TKey, V: TValue> Batch<K, V> {
+    /// the tree before the flush
+    pub fn before(&self) -> &ArcRadixTree<K, V> {
+        &self.v0
+    }
+
+    /// the tree after the flush
+    pub fn after(&self) -> &ArcRadixTree<K, V> {
+        &self.v1
+    }
+
+    /// all entries whose keys are in the new tree but not in the old tree
+    pub fn added(&self) -> ArcRadixTree<K, V> {
+        let mut res = self.v1.clone();
+        res.difference_with(&self.v0);
+        res
+    }
+
+    /// all entries whose keys are in the old tree but not in the new tree
+    pub fn removed(&self) -> ArcRadixTree<K, V> {
+        let mut res = self.v0.clone();
+        res.difference_with(&self.v1);
+        res
+    }
+
+    /// all changes from the old tree to the new tree, in key order
+    pub fn changes(&self) -> Vec<Change<K, V>>
+    where
+        V: PartialEq,
+    {
+        self.v0.diff(&self.v1)
+    }
+}
+
+/// A shared serialize registry that can be persisted across multiple serializations
+#[derive(Debug, Default)]
+pub struct SharedSerializeMap2 {
+    /// mapping from the rc/arc to the position in the buffer
+    shared_resolvers: hash_map::HashMap<*const u8, usize>,
+}
+
+impl Fallible for SharedSerializeMap2 {
+    type Error = SharedSerializeMapError;
+}
+
+impl SharedSerializeRegistry for SharedSerializeMap2 {
+    fn get_shared_ptr(&self, value: *const u8) -> Option<usize> {
+        self.shared_resolvers.get(&value).copied()
+    }
+
+    fn add_shared_ptr(
+        &mut self,
+        value: *const u8,
+        pos: usize,
+    ) -> std::result::Result<(), Self::Error> {
+        match self.shared_resolvers.entry(value) {
+            hash_map::Entry::Occupied(_) => {
+                Err(SharedSerializeMapError::DuplicateSharedPointer(value))
+            }
+            hash_map::Entry::Vacant(e) => {
+                e.insert(pos);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A shared deserialize registry that can be converted into a [SharedSerializeMap2]
+#[derive(Default)]
+pub struct SharedDeserializeMap2 {
+    /// mapping from the position in the buffer to the rc/arc
+    shared_pointers: hash_map::HashMap<*const u8, Box<dyn SharedPointer>>,
+}
+
+impl SharedDeserializeMap2 {
+    /// create a serialize map that maps the deserialized shared pointers to their offset from `base`
+    pub fn to_shared_serializer_map(&self, base: *const u8) -> SharedSerializeMap2 {
+        let shared_resolvers = self
+            .shared_pointers
+            .iter()
+            .map(|(k, v)| {
+                let offset: usize = (*k as usize) - (base as usize);
+                let address = v.data_address() as *const u8;
+                (address, offset)
+            })
+            .collect();
+        SharedSerializeMap2 { shared_resolvers }
+    }
+}
+
+impl Fallible for SharedDeserializeMap2 {
+    type Error = SharedDeserializeMapError;
+}
+
+/// these are safe, because a *const u8 is safe to send and sync
+///
+/// see discussion in https://internals.rust-lang.org/t/shouldnt-pointers-be-send-sync-or/8818
+unsafe impl Send for SharedSerializeMap2 {}
+unsafe impl Sync for SharedSerializeMap2 {}
+unsafe impl Send for SharedDeserializeMap2 {}
+unsafe impl Sync for SharedDeserializeMap2 {}
+
+impl SharedDeserializeRegistry for SharedDeserializeMap2 {
+    fn get_shared_ptr(&mut self, ptr: *const u8) -> Option<&dyn SharedPointer> {
+        self.shared_pointers.get(&ptr).map(|p| p.as_ref())
+    }
+
+    fn add_shared_ptr(
+        &mut self,
+        ptr: *const u8,
+        shared: Box<dyn SharedPointer>,
+    ) -> std::result::Result<(), Self::Error> {
+        match self.shared_pointers.entry(ptr) {
+            hash_map::Entry::Occupied(_) => {
+                Err(SharedDeserializeMapError::DuplicateSharedPointer(ptr))
+            }
+            hash_map::Entry::Vacant(e) => {
+                e.insert(shared);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A validator for the data of a radix db
+///
+/// The default rkyv validator requires sibling subtrees to be laid out in order. This does not hold for
+/// a radix db, since unchanged subtrees are not written again on flush, but referenced from earlier records.
+///
+/// This validator only requires every subtree to be located before the object that refers to it, which
+/// still rules out cycles. Shared subtrees are checked only once.
+#[derive(Debug)]
+pub struct RadixDbValidator<'a> {
+    inner: DefaultValidator<'a>,
+    subtree_range: Range<*const u8>,
+    depth: usize,
+}
+
+/// A subtree range that has to be restored when popping
+pub struct SavedRange {
+    range: Range<*const u8>,
+    depth: usize,
+}
+
+impl<'a> RadixDbValidator<'a> {
+    /// create a new validator for the given data
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            inner: DefaultValidator::new(bytes),
+            subtree_range: bytes.as_ptr_range(),
+            depth: 0,
+        }
+    }
+
+    fn push(&mut self, range: Range<*const u8>) -> SavedRange {
+        let res = SavedRange {
+            range: std::mem::replace(&mut self.subtree_range, range),
+            depth: self.depth,
+        };
+        self.depth += 1;
+        res
+    }
+
+    fn pop(&mut self, saved: SavedRange) -> std::result::Result<(), DefaultValidatorError> {
+        if self.depth != saved.depth + 1 {
+            return Err(DefaultValidatorError::ArchiveError(
+                ArchiveError::RangePoppedOutOfOrder {
+                    expected_depth: self.depth - 1,
+                    actual_depth: saved.depth,
+                },
+            ));
+        }
+        self.subtree_range = saved.range;
+        self.depth = saved.depth;
+        Ok(())
+    }
+}
+
+impl<'a> Fallible for RadixDbValidator<'a> {
+    type Error = DefaultValidatorError;
+}
+
+impl<'a> ArchiveContext for RadixDbValidator<'a> {
+    type PrefixRange = SavedRange;
+    type SuffixRange = SavedRange;
+
+    unsafe fn bounds_check_ptr(
+        &mut self,
+        base: *const u8,
+        offset: isize,
+    ) -> std::result::Result<*const u8, Self::Error> {
+        self.inner.bounds_check_ptr(base, offset)
+    }
+
+    unsafe fn bounds_check_layout(
+        &mut self,
+        data_address: *const u8,
+        layout: &Layout,
+    ) -> std::result::Result<(), Self::Error> {
+        self.inner.bounds_check_layout(data_address, layout)
+    }
+
+    unsafe fn bounds_check_subtree_ptr_layout(
+        &mut self,
+        data_address: *const u8,
+        layout: &Layout,
+    ) -> std::result::Result<(), Self::Error> {
+        let Range { start, end } = self.subtree_range;
+        if data_address < start || data_address > end {
+            Err(DefaultValidatorError::ArchiveError(
+                ArchiveError::SubtreePointerOutOfBounds {
+                    ptr: data_address,
+                    subtree_range: self.subtree_range.clone(),
+                },
+            ))
+        } else if (end as usize) - (data_address as usize) < layout.size() {
+            Err(DefaultValidatorError::ArchiveError(
+                ArchiveError::SubtreePointerOverrun {
+                    ptr: data_address,
+                    size: layout.size(),
+                    subtree_range: self.subtree_range.clone(),
+                },
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    unsafe fn push_prefix_subtree_range(
+        &mut self,
+        root: *const u8,
+        _end: *const u8,
+    ) -> std::result::Result<Self::PrefixRange, Self::Error> {
+        // everything the object refers to must be before the object itself,
+        // but unlike the default validator we allow referring to anything before it
+        Ok(self.push(self.subtree_range.start..root))
+    }
+
+    fn pop_prefix_range(
+        &mut self,
+        range: Self::PrefixRange,
+    ) -> std::result::Result<(), Self::Error> {
+        self.pop(range)
+    }
+
+    unsafe fn push_suffix_subtree_range(
+        &mut self,
+        start: *const u8,
+        root: *const u8,
+    ) -> std::result::Result<Self::SuffixRange, Self::Error> {
+        Ok(self.push(start..root))
+    }
+
+    fn pop_suffix_range(
+        &mut self,
+        range: Self::SuffixRange,
+    ) -> std::result::Result<(), Self::Error> {
+        self.pop(range)
+    }
+
+    fn finish(&mut self) -> std::result::Result<(), Self::Error> {
+        if self.depth != 0 {
+            Err(DefaultValidatorError::ArchiveError(
+                ArchiveError::UnpoppedSubtreeRanges {
+                    last_range: self.depth - 1,
+                },
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn wrap_layout_error(error: LayoutError) -> Self::Error {
+        DefaultValidator::wrap_layout_error(error)
+    }
+}
+
+impl<'a> SharedContext for RadixDbValidator<'a> {
+    fn register_shared_ptr(
+        &mut self,
+        ptr: *const u8,
+        type_id: TypeId,
+    ) -> std::result::Result<bool, Self::Error> {
+        self.inner.register_shared_ptr(ptr, type_id)
+    }
+}
+
+/// Abstraction of the storage for a [RadixDb]
+pub trait Storage {
+    /// appends to a file. Should only return when the data is safely on disk (flushed)!
+    /// appending will usually be done in large chunks.
+    /// appending to a non existing file creates it.
+    /// appending an empty chunk is a noop.
+    fn append(&self, file: &str, chunk: &[u8]) -> io::Result<()>;
+
+    /// load a file. The callback will get to look at the data and do something with it.
+    /// loading a non-existing file is like loading an empty file. It will not create the file.
+    ///
+    /// the data passed to the callback must be aligned to 16 bytes.
+    fn load<T>(&self, file: &str, f: impl FnMut(&[u8]) -> T) -> io::Result<T>;
+
+    /// atomically move a file. target will be atomically overwritten.
+    /// if the source file does not exist, the target file will be deleted.
+    fn mv(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// remove a file. removing a non-existing file is a noop.
+    fn remove(&self, file: &str) -> io::Result<()>;
+}
+
+/// In memory storage, mostly for testing
+///
+/// Clones share the same data.
+#[derive(Debug, Default, Clone)]
+pub struct MemStorage {
+    data: Arc<Mutex<BTreeMap<String, AlignedVec>>>,
+}
+
+impl Storage for MemStorage {
+    fn append(&self, file: &str, chunk: &[u8]) -> io::Result<()> {
+        if !chunk.is_empty() {
+            let mut data = self.data.lock();
+            let vec = if let Some(vec) = data.get_mut(file) {
+                vec
+            } else {
+                data.entry(file.to_owned()).or_default()
+            };
+            vec.extend_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    fn load<T>(&self, file: &str, mut f: impl FnMut(&[u8]) -> T) -> io::Result<T> {
+        let data = self.data.lock();
+        let res = if let Some(vec) = data.get(file) {
+            f(vec)
+        } else {
+            f(&[])
+        };
+        Ok(res)
+    }
+
+    fn mv(&self, from: &str, to: &str) -> io::Result<()> {
+        if from != to {
+            let mut data = self.data.lock();
+            if let Some(vec) = data.remove(from) {
+                if !vec.is_empty() {
+                    data.insert(to.to_owned(), vec);
+                } else {
+                    data.remove(to);
+                }
+            } else {
+                data.remove(to);
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, file: &str) -> io::Result<()> {
+        self.data.lock().remove(file);
+        Ok(())
+    }
+}
+
+/// Storage in files in a base directory
+#[derive(Debug, Default, Clone)]
+pub struct FileStorage {
+    base: PathBuf,
+}
+
+impl FileStorage {
+    /// create a new file storage in the given base directory
+    pub fn new(base: impl AsRef<std::path::Path>) -> Self {
+        Self {
+            base: base.as_ref().to_path_buf(),
+        }
+    }
+
+    fn remove_path(&self, path: &std::path::Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn append(&self, file: &str, chunk: &[u8]) -> io::Result<()> {
+        if !chunk.is_empty() {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.base.join(file))?;
+            file.write_all(chunk)?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn load<T>(&self, file: &str, mut f: impl FnMut(&[u8]) -> T) -> io::Result<T> {
+        let res = match fs::File::open(self.base.join(file)) {
+            Ok(mut file) => {
+                // read into an aligned buffer, since rkyv needs aligned data
+                let mut data = AlignedVec::new();
+                let mut buf = [0u8; 1 << 16];
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    data.extend_from_slice(&buf[..n]);
+                }
+                f(&data)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => f(&[]),
+            Err(e) => return Err(e),
+        };
+        Ok(res)
+    }
+
+    fn mv(&self, from: &str, to: &str) -> io::Result<()> {
+        if from != to {
+            let from = self.base.join(from);
+            let to = self.base.join(to);
+            match fs::rename(from, &to) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => self.remove_path(&to)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, file: &str) -> io::Result<()> {
+        self.remove_path(&self.base.join(file))
+    }
+}
+
+/// The serializer used by [RadixDb]
+pub type RadixDbSerializer<'a> = CompositeSerializer<
+    WriteSerializer<&'a mut AlignedVec>,
+    FallbackScratch<HeapScratch<256>, AllocScratch>,
+    SharedSerializeMap2,
+>;
+
+/// magic number at the start of each record header
+const MAGIC: u64 = u64::from_le_bytes(*b"radixdb0");
+
+/// size of a record header. A multiple of the 16 byte alignment of the data.
+const HEADER_SIZE: usize = 32;
+
+/// Header of a single record, written in front of each flushed tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RecordHeader {
+    /// length of the record data, excluding the header
+    len: u64,
+    /// absolute position of the root in the file
+    root: u64,
+    /// checksum of root and record data
+    checksum: u64,
+}
+
+impl RecordHeader {
+    fn new(root: usize, data: &[u8]) -> Self {
+        Self {
+            len: data.len() as u64,
+            root: root as u64,
+            checksum: checksum(root as u64, data),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut res = [0u8; HEADER_SIZE];
+        res[0..8].copy_from_slice(&MAGIC.to_le_bytes());
+        res[8..16].copy_from_slice(&self.len.to_le_bytes());
+        res[16..24].copy_from_slice(&self.root.to_le_bytes());
+        res[24..32].copy_from_slice(&self.checksum.to_le_bytes());
+        res
+    }
+
+    /// read a header from the start of data. Returns None if the header is damaged.
+    fn read(data: &[u8]) -> Option<Self> {
+        let field = |i: usize| -> Option<u64> {
+            let bytes = data.get(i * 8..(i + 1) * 8)?;
+            Some(u64::from_le_bytes(bytes.try_into().ok()?))
+        };
+        if field(0)? != MAGIC {
+            return None;
+        }
+        Some(Self {
+            len: field(1)?,
+            root: field(2)?,
+            checksum: field(3)?,
+        })
+    }
+}
+
+/// fnv-1a hash of the root position and the record data
+fn checksum(root: u64, data: &[u8]) -> u64 {
+    root.to_le_bytes()
+        .iter()
+        .chain(data)
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ (*byte as u64)).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Location of an intact record in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Record {
+    /// absolute position of the root
+    root: usize,
+    /// absolute position of the end of the record
+    end: usize,
+}
+
+/// Scan all records in data, and return the positions of all intact records.
+///
+/// Scanning stops at the first damaged record.
+fn scan_records(data: &[u8]) -> Vec<Record> {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while let Some(header) = RecordHeader::read(&data[pos..]) {
+        let start = pos + HEADER_SIZE;
+        let end = match start.checked_add(header.len as usize) {
+            Some(end) if end <= data.len() => end,
+            _ => break,
+        };
+        let root = header.root as usize;
+        if root < start
+            || root >= end
+            || checksum(header.root, &data[start..end]) != header.checksum
+        {
+            break;
+        }
+        records.push(Record { root, end });
+        pos = end;
+    }
+    records
+}
+
+/// A persistent radix tree database
+///
+/// Modify the tree using [tree_mut](RadixDb::tree_mut), then persist the changes using [flush](RadixDb::flush).
+#[allow(clippy::type_complexity)]
+pub struct RadixDb<K: TKey, V: TValue, S> {
+    storage: S,
+    name: String,
+    serializers: Option<(
+        SharedSerializeMap2,
+        BTreeMap<usize, Arc<Vec<ArcRadixTree<K, V>>>>,
+    )>,
+    pos: usize,
+    tree: ArcRadixTree<K, V>,
+    watchers: Vec<UnboundedSender<ArcRadixTree<K, V>>>,
+}
+
+impl<K: TKey, V: TValue> RadixDb<K, V, MemStorage>
+where
+    Archived<K>: Deserialize<K, SharedDeserializeMap2>,
+    Archived<V>: Deserialize<V, SharedDeserializeMap2>,
+    Archived<ArcRadixTree<K, V>>: for<'x> CheckBytes<RadixDbValidator<'x>>,
+{
+    /// create a new, empty in memory db
+    pub fn memory(name: impl Into<String>) -> Result<Self> {
+        RadixDb::load(MemStorage::default(), name)
+    }
+}
+
+impl<K: TKey, V: TValue> RadixDb<K, V, FileStorage>
+where
+    Archived<K>: Deserialize<K, SharedDeserializeMap2>,
+    Archived<V>: Deserialize<V, SharedDeserializeMap2>,
+    Archived<ArcRadixTree<K, V>>: for<'x> CheckBytes<RadixDbValidator<'x>>,
+{
+    /// open a file based db in the given directory
+    pub fn open(base: impl AsRef<std::path::Path>, name: impl Into<String>) -> Result<Self> {
+        RadixDb::load(FileStorage::new(base), name)
+    }
+}
+
+impl<K: TKey, V: TValue, S: Storage> RadixDb<K, V, S> {
+    /// the underlying storage
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// the name of the file in the storage
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// the current size of the file in the storage, in bytes
+    ///
+    /// this is not the number of entries, see [tree](RadixDb::tree) for that.
+    pub fn persisted_len(&self) -> usize {
+        self.pos
+    }
+
+    /// true if nothing has been persisted yet, even if the current tree is not empty
+    pub fn is_unpersisted(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// the current, possibly not yet flushed tree
+    pub fn tree(&self) -> &ArcRadixTree<K, V> {
+        &self.tree
+    }
+
+    /// mutable access to the current tree. Changes will be persisted on the next flush.
+    pub fn tree_mut(&mut self) -> &mut ArcRadixTree<K, V> {
+        &mut self.tree
+    }
+
+    /// load a db from the given storage
+    ///
+    /// the data is validated before it is used, including all shared subtrees.
+    ///
+    /// if the file ends with a damaged record, e.g. from an interrupted flush, the damaged
+    /// record is discarded. If the last intact record does not pass validation, the db is loaded
+    /// from the last record that does, and everything after it is discarded. Each record is
+    /// validated on its own, so it can not refer to data after it.
+    ///
+    /// if the file is not empty, but does not contain a single intact record, loading fails and
+    /// the file is left unchanged.
+    pub fn load(storage: S, name: impl Into<String>) -> Result<Self>
+    where
+        Archived<K>: Deserialize<K, SharedDeserializeMap2>,
+        Archived<V>: Deserialize<V, SharedDeserializeMap2>,
+        Archived<ArcRadixTree<K, V>>: for<'x> CheckBytes<RadixDbValidator<'x>>,
+    {
+        let name = name.into();
+        let (tree, map, arcs, pos, damaged) = storage.load(&name, |data| -> Result<_> {
+            let mut valid = None;
+            let mut error = None;
+            for record in scan_records(data).into_iter().rev() {
+                // a valid record only refers to itself and earlier records, never to the tail
+                let data = &data[..record.end];
+                let mut validator = RadixDbValidator::new(data);
+                match check_archived_value_with_context::<ArcRadixTree<K, V>, _>(
+                    data,
+                    record.root,
+                    &mut validator,
+                ) {
+                    Ok(tree) => {
+                        valid = Some((tree, record.end));
+                        break;
+                    }
+                    Err(e) => {
+                        error.get_or_insert_with(|| e.to_string());
+                    }
+                }
+            }
+            let (tree, pos) = match (valid, error) {
+                (Some(valid), _) => valid,
+                // intact records exist, but none of them is valid. Something is seriously wrong.
+                (None, Some(error)) => return Err(RadixDbError::Validation(error)),
+                // the file is empty, so this is a new db
+                (None, None) if data.is_empty() => {
+                    return Ok((
+                        Default::default(),
+                        Default::default(),
+                        Default::default(),
+                        0,
+                        None,
+                    ))
+                }
+                // not a single intact record. This is not a radix db or the very first flush was
+                // interrupted. Either way, leave the file alone.
+                (None, None) => {
+                    return Err(RadixDbError::Validation("no intact record".to_string()))
+                }
+            };
+            // if there is a damaged tail, keep a copy of the valid part so it can be rewritten
+            let damaged = if pos < data.len() {
+                let mut intact = AlignedVec::new();
+                intact.extend_from_slice(&data[..pos]);
+                Some(intact)
+            } else {
+                None
+            };
+            let mut deserializer = SharedDeserializeMap2::default();
+            let tree: ArcRadixTree<K, V> = tree
+                .deserialize(&mut deserializer)
+                .map_err(|e| RadixDbError::Deserialize(e.to_string()))?;
+            let map = deserializer.to_shared_serializer_map(data.as_ptr());
+            let mut arcs = BTreeMap::default();
+            tree.all_arcs(&mut arcs);
+            Ok((tree, map, arcs, pos, damaged))
+        })??;
+        if let Some(intact) = damaged {
+            // truncate the file to the intact part, so we can safely append again
+            let tmp = format!("{}.tmp", name);
+            storage.remove(&tmp)?;
+            storage.append(&tmp, &intact)?;
+            storage.mv(&tmp, &name)?;
+        }
+        Ok(Self {
+            tree,
+            name,
+            storage,
+            pos,
+            serializers: Some((map, arcs)),
+            watchers: Default::default(),
+        })
+    }
+
+    /// watch the db. The receiver will get the new tree after every flush or vacuum.
+    pub fn watch(&mut self) -> UnboundedReceiver<ArcRadixTree<K, V>> {
+        let (s, r) = futures::channel::mpsc::unbounded();
+        self.watchers.push(s);
+        r
+    }
+
+    /// watch all keys starting with a prefix. The stream will get a batch after every flush or vacuum.
+    pub fn watch_prefix(&mut self, prefix: Vec<K>) -> BoxStream<'static, Batch<K, V>> {
+        let tree = self.tree().filter_prefix(&prefix);
+        self.watch()
+            .scan(tree, move |prev, curr| {
+                let v0 = std::mem::replace(prev, curr.filter_prefix(&prefix));
+                let v1 = prev.clone();
+                future::ready(Some(Batch { v0, v1 }))
+            })
+            .boxed()
+    }
+
+    /// watch all keys starting with a prefix, and get a stream of individual changes.
+    ///
+    /// the changes of each flush or vacuum are reported in key order. Unchanged subtrees are
+    /// skipped, so the cost is proportional to the size of the change, not the size of the tree.
+    pub fn changes(&mut self, prefix: Vec<K>) -> BoxStream<'static, Change<K, V>>
+    where
+        V: PartialEq,
+    {
+        self.watch_prefix(prefix)
+            .flat_map(|batch| stream::iter(batch.changes()))
+            .boxed()
+    }
+
+    fn notify(&mut self) {
+        let tree = self.tree.clone();
+        self.watchers
+            .retain(|sender| sender.unbounded_send(tree.clone()).is_ok())
+    }
+}
+
+impl<K, V, S> RadixDb<K, V, S>
+where
+    K: TKey + for<'x> Serialize<RadixDbSerializer<'x>>,
+    V: TValue + for<'x> Serialize<RadixDbSerializer<'x>>,
+    S: Storage,
+{
+    /// write just the current tree to a new file, and atomically replace the old file
+    pub fn vacuum(&mut self) -> Result<()> {
+        let (record, map) = serialize_record(&self.tree, 0, Default::default())?;
+        // compute just the current arcs of the current tree
+        let mut arcs = BTreeMap::default();
+        self.tree.all_arcs(&mut arcs);
+        // store the new file and the new arcs
+        let tmp = format!("{}.tmp", self.name);
+        // make sure we don't append to a leftover tmp file
+        self.storage.remove(&tmp)?;
+        self.storage.append(&tmp, &record)?;
+        self.storage.mv(&tmp, &self.name)?;
+        self.pos = record.len();
+        self.serializers = Some((map, arcs));
+        self.notify();
+        Ok(())
+    }
+
+    /// persist the current tree by appending it to the file
+    ///
+    /// parts of the tree that have already been persisted will not be written again.
+    pub fn flush(&mut self) -> Result<()> {
+        let (map, mut arcs) = self.serializers.take().unwrap_or_default();
+        let (record, map) = serialize_record(&self.tree, self.pos, map)?;
+        self.tree.all_arcs(&mut arcs);
+        self.storage.append(&self.name, &record)?;
+        self.pos += record.len();
+        self.serializers = Some((map, arcs));
+        self.notify();
+        Ok(())
+    }
+}
+
+/// serialize a tree as a record starting at absolute position pos
+fn serialize_record<K, V>(
+    tree: &ArcRadixTree<K, V>,
+    pos: usize,
+    map: SharedSerializeMap2,
+) -> Result<(AlignedVec, SharedSerializeMap2)>
+where
+    K: TKey + for<'x> Serialize<RadixDbSerializer<'x>>,
+    V: TValue + for<'x> Serialize<RadixDbSerializer<'x>>,
+{
+    let mut data = AlignedVec::new();
+    let mut serializer = CompositeSerializer::new(
+        WriteSerializer::with_pos(&mut data, pos + HEADER_SIZE),
+        Default::default(),
+        map,
+    );
+    let root = serializer
+        .serialize_value(tree)
+        .map_err(|e| RadixDbError::Serialize(e.to_string()))?;
+    let (_, _, map) = serializer.into_components();
+    let mut record = AlignedVec::with_capacity(HEADER_SIZE + data.len());
+    record.extend_from_slice(&RecordHeader::new(root, &data).to_bytes());
+    record.extend_from_slice(&data);
+    Ok((record, map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::radix_tree::AbstractRadixTree;
+
+    type Db<S> = RadixDb<u8, u32, S>;
+
+    fn key(i: u32) -> Vec<u8> {
+        format!("{}-{}", i % 10, i).into_bytes()
+    }
+
+    fn content<S: Storage>(db: &Db<S>) -> Vec<(Vec<u8>, u32)> {
+        db.tree().iter().map(|(k, v)| (k.to_vec(), *v)).collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "vec-collections-radix-db-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    type Edit<'a> = &'a dyn Fn(&mut Vec<u8>);
+
+    fn mem_edit(storage: &MemStorage) -> impl Fn(Edit) {
+        let data = storage.data.clone();
+        move |f| {
+            let mut data = data.lock();
+            let file = data.get_mut("test").unwrap();
+            let mut tmp = file.to_vec();
+            f(&mut tmp);
+            let mut res = AlignedVec::new();
+            res.extend_from_slice(&tmp);
+            *file = res;
+        }
+    }
+
+    fn file_edit(dir: &std::path::Path) -> impl Fn(Edit) {
+        let path = dir.join("test");
+        move |f| {
+            let mut data = fs::read(&path).unwrap();
+            f(&mut data);
+            fs::write(&path, data).unwrap();
+        }
+    }
+
+    /// flush a few batches, and check that loading gives the same result after each step
+    fn flush_load<S: Storage + Clone>(storage: S) -> Result<()> {
+        let mut db: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        assert!(db.is_unpersisted());
+        for i in 0..10 {
+            for j in 0..100 {
+                db.tree_mut().insert(&key(i * 100 + j), i * 100 + j);
+            }
+            db.tree_mut().remove(&key(i * 10));
+            if i % 4 == 3 {
+                db.vacuum()?;
+            } else {
+                db.flush()?;
+            }
+            let db2: Db<S> = RadixDb::load(storage.clone(), "test")?;
+            assert_eq!(content(&db), content(&db2));
+            assert_eq!(db.persisted_len(), db2.persisted_len());
+        }
+        // a flush after vacuum or load must produce a loadable file
+        let mut db2: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        db2.tree_mut().insert(b"x", 1);
+        db2.flush()?;
+        let db3: Db<S> = RadixDb::load(storage, "test")?;
+        assert_eq!(content(&db2), content(&db3));
+        Ok(())
+    }
+
+    /// overwrite the data of the record with the given index, but keep the checksum intact
+    fn corrupt_record(data: &mut [u8], index: usize) {
+        let records = scan_records(data);
+        let start = if index == 0 {
+            0
+        } else {
+            records[index - 1].end
+        };
+        let end = records[index].end;
+        data[start + HEADER_SIZE..end]
+            .iter_mut()
+            .for_each(|x| *x = 0xff);
+        let header = RecordHeader::new(records[index].root, &data[start + HEADER_SIZE..end]);
+        data[start..start + HEADER_SIZE].copy_from_slice(&header.to_bytes());
+    }
+
+    /// flush twice, damage the tail, and check that the db recovers the first flush
+    fn recover<S: Storage + Clone>(storage: S, edit: impl Fn(Edit)) -> Result<()> {
+        let mut db: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        db.tree_mut().insert(b"a", 1);
+        db.flush()?;
+        let expected = content(&db);
+        let len = db.persisted_len();
+        db.tree_mut().insert(b"b", 2);
+        db.flush()?;
+        // the second record is truncated somewhere
+        let truncated = len + (db.persisted_len() - len) / 2;
+        edit(&|data| data.truncate(truncated));
+        let mut db: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        assert_eq!(content(&db), expected);
+        assert_eq!(db.persisted_len(), len);
+        // the damaged tail is gone, so appending works again
+        db.tree_mut().insert(b"c", 3);
+        db.flush()?;
+        let db2: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        assert_eq!(content(&db2), content(&db));
+        // even the first record is damaged. Loading fails, and the file is not touched
+        edit(&|data| data.truncate(HEADER_SIZE / 2));
+        let res: Result<Db<S>> = RadixDb::load(storage.clone(), "test");
+        assert!(matches!(res, Err(RadixDbError::Validation(_))));
+        edit(&|data| assert_eq!(data.len(), HEADER_SIZE / 2));
+        // same for a file that is not a radix db at all
+        edit(&|data| *data = b"not a radix db".to_vec());
+        let res: Result<Db<S>> = RadixDb::load(storage, "test");
+        assert!(matches!(res, Err(RadixDbError::Validation(_))));
+        edit(&|data| assert_eq!(data.as_slice(), b"not a radix db"));
+        Ok(())
+    }
+
+    /// flush three times, corrupt the last record, and check that the db falls back to the second flush
+    fn validate<S: Storage + Clone>(storage: S, edit: impl Fn(Edit)) -> Result<()> {
+        let mut db: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        for i in 0..100 {
+            db.tree_mut().insert(&key(i), i);
+        }
+        db.flush()?;
+        db.tree_mut().insert(b"a", 1);
+        db.flush()?;
+        let expected = content(&db);
+        let len = db.persisted_len();
+        db.tree_mut().insert(b"b", 2);
+        db.flush()?;
+        edit(&|data| corrupt_record(data, 2));
+        let mut db: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        assert_eq!(content(&db), expected);
+        assert_eq!(db.persisted_len(), len);
+        // the corrupted record is gone, so appending works again
+        db.tree_mut().insert(b"c", 3);
+        db.flush()?;
+        let db2: Db<S> = RadixDb::load(storage.clone(), "test")?;
+        assert_eq!(content(&db2), content(&db));
+        // if no record is valid, loading fails
+        db.vacuum()?;
+        edit(&|data| corrupt_record(data, 0));
+        let res: Result<Db<S>> = RadixDb::load(storage, "test");
+        assert!(matches!(res, Err(RadixDbError::Validation(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn mem_flush_load() -> Result<()> {
+        flush_load(MemStorage::default())
+    }
+
+    #[test]
+    fn file_flush_load() -> Result<()> {
+        let dir = temp_dir("flush_load");
+        flush_load(FileStorage::new(&dir))?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn mem_recover() -> Result<()> {
+        let storage = MemStorage::default();
+        recover(storage.clone(), mem_edit(&storage))
+    }
+
+    #[test]
+    fn file_recover() -> Result<()> {
+        let dir = temp_dir("recover");
+        recover(FileStorage::new(&dir), file_edit(&dir))?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn mem_validate() -> Result<()> {
+        let storage = MemStorage::default();
+        validate(storage.clone(), mem_edit(&storage))
+    }
+
+    #[test]
+    fn file_validate() -> Result<()> {
+        let dir = temp_dir("validate");
+        validate(FileStorage::new(&dir), file_edit(&dir))?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn incremental_flush() -> Result<()> {
+        let mut db: Db<MemStorage> = RadixDb::memory("test")?;
+        for i in 0..1000 {
+            db.tree_mut().insert(&key(i), i);
+        }
+        db.flush()?;
+        let full = db.persisted_len();
+        db.tree_mut().insert(b"0-x", 0);
+        db.flush()?;
+        // only the changed path should be written
+        assert!(db.persisted_len() - full < full / 4);
+        db.vacuum()?;
+        assert!(db.persisted_len() < full + full / 4);
+        Ok(())
+    }
+
+    #[test]
+    fn changes() -> Result<()> {
+        let mut db: Db<MemStorage> = RadixDb::memory("test")?;
+        db.tree_mut().insert(b"a1", 1);
+        db.tree_mut().insert(b"b1", 1);
+        db.flush()?;
+        let stream = db.changes(b"a".to_vec());
+        db.tree_mut().insert(b"a1", 2);
+        db.tree_mut().insert(b"a2", 2);
+        db.tree_mut().insert(b"b2", 2);
+        db.flush()?;
+        // no change for the prefix
+        db.tree_mut().remove(b"b1");
+        db.flush()?;
+        db.tree_mut().remove(b"a1");
+        db.vacuum()?;
+        drop(db);
+        let changes = futures::executor::block_on(stream.collect::<Vec<_>>());
+        assert_eq!(
+            changes,
+            vec![
+                Change::Update {
+                    key: b"a1".to_vec(),
+                    old: 1,
+                    new: 2
+                },
+                Change::Insert {
+                    key: b"a2".to_vec(),
+                    value: 2
+                },
+                Change::Delete {
+                    key: b"a1".to_vec(),
+                    value: 2
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn watch_prefix() -> Result<()> {
+        let mut db: Db<MemStorage> = RadixDb::memory("test")?;
+        db.tree_mut().insert(b"a1", 1);
+        db.tree_mut().insert(b"b1", 1);
+        let stream = db.watch_prefix(b"a".to_vec());
+        db.tree_mut().insert(b"a2", 2);
+        db.tree_mut().insert(b"b2", 2);
+        db.flush()?;
+        db.tree_mut().remove(b"a1");
+        db.vacuum()?;
+        drop(db);
+        let keys =
+            |tree: ArcRadixTree<u8, u32>| tree.iter().map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+        let batches = futures::executor::block_on(stream.collect::<Vec<_>>());
+        assert_eq!(batches.len(), 2);
+        assert_eq!(keys(batches[0].added()), vec![b"a2".to_vec()]);
+        assert!(keys(batches[0].removed()).is_empty());
+        assert!(keys(batches[1].added()).is_empty());
+        assert_eq!(keys(batches[1].removed()), vec![b"a1".to_vec()]);
+        Ok(())
+    }
+}
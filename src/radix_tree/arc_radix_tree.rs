@@ -1,16 +1,20 @@
 use super::internals;
 use internals::{AbstractRadixTreeMut as _, Fragment};
 use lazy_static::lazy_static;
-use std::{collections::BTreeMap, sync::Arc};
-
-use super::{location, offset_from, AbstractRadixTree, RadixTree, TKey, TValue};
-use rkyv::{
-    de::SharedDeserializeRegistry,
-    ser::{ScratchSpace, Serializer, SharedSerializeRegistry},
-    vec::ArchivedVec,
-    Archive, Archived, Deserialize, Resolver, Serialize,
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::Arc,
 };
 
+#[cfg(feature = "merkle")]
+use super::merkle::MerkleHash;
+#[cfg(feature = "merkle")]
+use std::sync::OnceLock;
+
+use super::{common_prefix, location, AbstractRadixTree, RadixTree, TKey, TValue};
+
 lazy_static! {
     static ref EMPTY_ARC_VEC: Arc<Vec<u128>> = Arc::new(Vec::new());
 }
@@ -40,6 +44,9 @@ where
     prefix: Fragment<K>,
     value: Option<V>,
     children: Arc<Vec<Self>>,
+    /// cached content hash, computed on demand
+    #[cfg(feature = "merkle")]
+    hash: OnceLock<MerkleHash>,
 }
 
 impl<K: TKey, V: TValue> Default for ArcRadixTree<K, V> {
@@ -48,6 +55,8 @@ impl<K: TKey, V: TValue> Default for ArcRadixTree<K, V> {
             prefix: Default::default(),
             value: Default::default(),
             children: empty_arc(),
+            #[cfg(feature = "merkle")]
+            hash: OnceLock::new(),
         }
     }
 }
@@ -71,31 +80,48 @@ impl<K: TKey, V: TValue> AbstractRadixTree<K, V> for ArcRadixTree<K, V> {
 impl<K: TKey, V: TValue> internals::AbstractRadixTreeMut<K, V> for ArcRadixTree<K, V> {
     fn new(prefix: Fragment<K>, value: Option<V>, children: Vec<Self>) -> Self {
         let children = wrap_in_arc(children);
-        Self {
-            prefix,
-            value,
-            children,
-        }
+        Self::from_arc(prefix, value, children)
     }
 
+    // the hash depends on everything in the subtree, and every mutation of a subtree has to go through
+    // one of the accessors below for all nodes on the path, so the hash is dropped in all of them.
+
     fn value_mut(&mut self) -> &mut Option<V> {
+        self.invalidate_hash();
         &mut self.value
     }
 
-    fn prefix_mut(&mut self) -> &mut Fragment<K> {
-        &mut self.prefix
+    fn set_prefix(&mut self, prefix: Fragment<K>) {
+        self.invalidate_hash();
+        self.prefix = prefix;
     }
 
-    fn children_mut(&mut self) -> &mut Vec<Self> {
+    fn children_mut(&mut self) -> &mut [Self] {
+        self.invalidate_hash();
         // this is what makes the data structure copy on write.
         // If we are the sole owner, this will not allocate and be very cheap.
         // if there is another owner (e.g. an old snapshot), this will clone the array.
         //
         // cloning will shrink to fit
-        Arc::make_mut(self.children_arc_mut())
+        Arc::make_mut(self.children_arc_mut()).as_mut_slice()
+    }
+
+    fn edit_children<R>(&mut self, f: impl FnOnce(&mut Vec<Self>) -> R) -> R {
+        self.invalidate_hash();
+        f(Arc::make_mut(self.children_arc_mut()))
+    }
+
+    fn value_and_children_mut(&mut self) -> (&mut Option<V>, &mut [Self]) {
+        self.invalidate_hash();
+        (
+            &mut self.value,
+            Arc::make_mut(&mut self.children).as_mut_slice(),
+        )
     }
 }
 
+impl_std_traits!(mut [K: TKey, V: TValue] ArcRadixTree<K, V>);
+
 impl<K: TKey, V: TValue> From<RadixTree<K, V>> for ArcRadixTree<K, V> {
     fn from(value: RadixTree<K, V>) -> Self {
         let RadixTree {
@@ -109,7 +135,26 @@ impl<K: TKey, V: TValue> From<RadixTree<K, V>> for ArcRadixTree<K, V> {
 }
 
 impl<K: TKey, V: TValue> ArcRadixTree<K, V> {
-    fn children_arc(&self) -> &Arc<Vec<Self>> {
+    pub(crate) fn from_arc(
+        prefix: Fragment<K>,
+        value: Option<V>,
+        children: Arc<Vec<Self>>,
+    ) -> Self {
+        Self {
+            prefix,
+            value,
+            children,
+            #[cfg(feature = "merkle")]
+            hash: OnceLock::new(),
+        }
+    }
+
+    fn invalidate_hash(&mut self) {
+        #[cfg(feature = "merkle")]
+        self.hash.take();
+    }
+
+    pub(crate) fn children_arc(&self) -> &Arc<Vec<Self>> {
         &self.children
     }
 
@@ -130,192 +175,859 @@ impl<K: TKey, V: TValue> ArcRadixTree<K, V> {
     }
 }
 
-impl<K: TKey, V: TValue + Archive<Archived = V>> From<&ArchivedArcRadixTree<K, V>>
-    for ArcRadixTree<K, V>
-{
-    fn from(value: &ArchivedArcRadixTree<K, V>) -> Self {
-        let children = value.children().iter().map(Self::from).collect::<Vec<_>>();
-        let children = Arc::new(children);
-        ArcRadixTree {
-            prefix: value.prefix().into(),
-            value: value.value().cloned(),
-            children,
+/// children of a node, with each child identified by its prefix, value and the location of its children
+type MinimizeKey<K, V> = Vec<(Fragment<K>, Option<V>, usize)>;
+
+type Children<K, V> = Arc<Vec<ArcRadixTree<K, V>>>;
+
+/// state of a [minimize](ArcRadixTree::minimize) pass
+struct Minimizer<K: TKey, V: TValue> {
+    /// canonical children lists, by content
+    canonical: HashMap<MinimizeKey<K, V>, Children<K, V>>,
+    /// children lists that have already been visited, by location, to the canonical list
+    visited: HashMap<usize, Children<K, V>>,
+    /// the visited lists, kept alive so their locations stay unique during the pass
+    originals: Vec<Children<K, V>>,
+}
+
+impl<K: TKey + Hash, V: TValue + Hash + Eq> Minimizer<K, V> {
+    fn minimize(&mut self, node: &mut ArcRadixTree<K, V>) {
+        if node.children.is_empty() {
+            node.children = empty_arc();
+            return;
         }
+        let id = location(node.children.as_ref());
+        if let Some(canonical) = self.visited.get(&id) {
+            node.children = canonical.clone();
+            return;
+        }
+        // children are cheap to clone, and this leaves lists that are shared with other trees alone
+        let mut children = node.children().to_vec();
+        for child in &mut children {
+            self.minimize(child);
+        }
+        // since the children are already canonical, their children can be compared by location
+        let key = children
+            .iter()
+            .map(|child| {
+                let children = if child.children.is_empty() {
+                    0
+                } else {
+                    location(child.children.as_ref())
+                };
+                (child.prefix.clone(), child.value.clone(), children)
+            })
+            .collect();
+        // reuse the list if nothing changed, so minimizing a minimized tree does not allocate
+        let unchanged = children
+            .iter()
+            .zip(node.children().iter())
+            .all(|(a, b)| Arc::ptr_eq(&a.children, &b.children));
+        let original = &node.children;
+        let canonical = self
+            .canonical
+            .entry(key)
+            .or_insert_with(|| {
+                if unchanged {
+                    original.clone()
+                } else {
+                    Arc::new(children)
+                }
+            })
+            .clone();
+        let original = std::mem::replace(&mut node.children, canonical.clone());
+        self.originals.push(original);
+        self.visited.insert(id, canonical);
     }
 }
 
-impl<K: TKey, V: TValue + Archive<Archived = V>> AbstractRadixTree<K, V>
-    for ArchivedArcRadixTree<K, V>
-{
-    type Materialized = ArcRadixTree<K, V>;
+impl<K: TKey + Hash, V: TValue + Hash + Eq> ArcRadixTree<K, V> {
+    /// Share all structurally identical children lists in this tree, turning it into a DAG.
+    ///
+    /// This is useful for large static trees like word lists, where many subtrees are the same.
+    /// Since sharing is by `Arc`, it is preserved when serializing with rkyv, and the tree can still be
+    /// modified with copy on write as usual.
+    pub fn minimize(&mut self) {
+        let mut minimizer = Minimizer {
+            canonical: HashMap::new(),
+            visited: HashMap::new(),
+            originals: Vec::new(),
+        };
+        minimizer.minimize(self);
+    }
+}
 
-    fn prefix(&self) -> &[K] {
-        &self.prefix
+/// A change to a single key, as computed by [diff](ArcRadixTree::diff)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    /// a key that is only present in the new tree
+    Insert { key: Vec<K>, value: V },
+    /// a key that is present in both trees, but with different values
+    Update { key: Vec<K>, old: V, new: V },
+    /// a key that is only present in the old tree
+    Delete { key: Vec<K>, value: V },
+}
+
+impl<K, V> Change<K, V> {
+    /// the key that was changed
+    pub fn key(&self) -> &[K] {
+        match self {
+            Change::Insert { key, .. }
+            | Change::Update { key, .. }
+            | Change::Delete { key, .. } => key,
+        }
     }
+}
 
-    fn value(&self) -> Option<&V> {
-        self.value.as_ref()
+impl<K: TKey, V: TValue + PartialEq> ArcRadixTree<K, V> {
+    /// compute the changes from this tree to that tree, in key order
+    ///
+    /// subtrees that are shared between the two trees are skipped, so the cost is proportional
+    /// to the size of the change, not to the size of the trees.
+    pub fn diff(&self, that: &Self) -> Vec<Change<K, V>> {
+        let mut res = Vec::new();
+        diff0(&mut Vec::new(), self, 0, that, 0, &mut res);
+        res
     }
+}
 
-    fn children(&self) -> &[Self] {
-        &self.children
+/// emit an insert or delete for every entry of a node, with the prefix starting at offset
+fn emit_all<K: TKey, V: TValue>(
+    path: &mut Vec<K>,
+    node: &ArcRadixTree<K, V>,
+    offset: usize,
+    change: &impl Fn(Vec<K>, V) -> Change<K, V>,
+    res: &mut Vec<Change<K, V>>,
+) {
+    let n = path.len();
+    path.extend_from_slice(&node.prefix()[offset..]);
+    if let Some(value) = node.value() {
+        res.push(change(path.clone(), value.clone()));
     }
+    for child in node.children() {
+        emit_all(path, child, 0, change, res);
+    }
+    path.truncate(n);
 }
 
-pub struct ArcRadixTreeResolver<K: TKey, V: TValue> {
-    prefix: Resolver<Vec<K>>,
-    value: Resolver<Option<V>>,
-    children: Resolver<Arc<Vec<ArcRadixTree<K, V>>>>,
+fn delete_all<K: TKey, V: TValue>(
+    path: &mut Vec<K>,
+    node: &ArcRadixTree<K, V>,
+    offset: usize,
+    res: &mut Vec<Change<K, V>>,
+) {
+    emit_all(
+        path,
+        node,
+        offset,
+        &|key, value| Change::Delete { key, value },
+        res,
+    )
 }
 
-#[repr(C)]
-pub struct ArchivedArcRadixTree<K, V>
-where
-    K: TKey,
-    V: TValue,
-{
-    prefix: Archived<Vec<K>>,
-    value: Archived<Option<V>>,
-    children: Archived<Arc<Vec<ArcRadixTree<K, V>>>>,
+fn insert_all<K: TKey, V: TValue>(
+    path: &mut Vec<K>,
+    node: &ArcRadixTree<K, V>,
+    offset: usize,
+    res: &mut Vec<Change<K, V>>,
+) {
+    emit_all(
+        path,
+        node,
+        offset,
+        &|key, value| Change::Insert { key, value },
+        res,
+    )
 }
 
-impl<'a, K: TKey, V: TValue> Archive for ArcRadixTree<K, V> {
-    type Archived = ArchivedArcRadixTree<K, V>;
+fn diff_values<K: TKey, V: TValue + PartialEq>(
+    path: &[K],
+    a: Option<&V>,
+    b: Option<&V>,
+    res: &mut Vec<Change<K, V>>,
+) {
+    let key = || path.to_vec();
+    match (a, b) {
+        (Some(old), Some(new)) if old != new => res.push(Change::Update {
+            key: key(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+        (Some(value), None) => res.push(Change::Delete {
+            key: key(),
+            value: value.clone(),
+        }),
+        (None, Some(value)) => res.push(Change::Insert {
+            key: key(),
+            value: value.clone(),
+        }),
+        _ => {}
+    }
+}
 
-    type Resolver = ArcRadixTreeResolver<K, V>;
+/// diff of two nodes at the same position, where the prefix of a starts at ao and the prefix of b starts at bo
+fn diff0<K: TKey, V: TValue + PartialEq>(
+    path: &mut Vec<K>,
+    a: &ArcRadixTree<K, V>,
+    ao: usize,
+    b: &ArcRadixTree<K, V>,
+    bo: usize,
+    res: &mut Vec<Change<K, V>>,
+) {
+    let pa = &a.prefix()[ao..];
+    let pb = &b.prefix()[bo..];
+    let n = common_prefix(pa, pb);
+    let len = path.len();
+    if n == pa.len() && n == pb.len() {
+        path.extend_from_slice(pa);
+        diff_values(path, a.value(), b.value(), res);
+        // this is where we skip shared subtrees
+        if !Arc::ptr_eq(a.children_arc(), b.children_arc()) {
+            let (ac, bc) = (a.children(), b.children());
+            let (mut i, mut j) = (0, 0);
+            while i < ac.len() || j < bc.len() {
+                let ordering = match (ac.get(i), bc.get(j)) {
+                    (Some(ca), Some(cb)) => ca.prefix()[0].cmp(&cb.prefix()[0]),
+                    (Some(_), None) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                match ordering {
+                    Ordering::Less => {
+                        delete_all(path, &ac[i], 0, res);
+                        i += 1;
+                    }
+                    Ordering::Greater => {
+                        insert_all(path, &bc[j], 0, res);
+                        j += 1;
+                    }
+                    Ordering::Equal => {
+                        diff0(path, &ac[i], 0, &bc[j], 0, res);
+                        i += 1;
+                        j += 1;
+                    }
+                }
+            }
+        }
+    } else if n == pa.len() {
+        // a ends within b. a's value is gone, and b continues in the children of a
+        path.extend_from_slice(pa);
+        diff_values(path, a.value(), None, res);
+        diff_children(path, a.children(), b, bo + n, false, res);
+    } else if n == pb.len() {
+        // b ends within a. b's value is new, and a continues in the children of b
+        path.extend_from_slice(pb);
+        diff_values(path, None, b.value(), res);
+        diff_children(path, b.children(), a, ao + n, true, res);
+    } else if pa[n] < pb[n] {
+        delete_all(path, a, ao, res);
+        insert_all(path, b, bo, res);
+    } else {
+        insert_all(path, b, bo, res);
+        delete_all(path, a, ao, res);
+    }
+    path.truncate(len);
+}
 
-    unsafe fn resolve(&self, pos: usize, resolver: Self::Resolver, out: *mut Self::Archived) {
-        let ArcRadixTreeResolver {
-            prefix,
-            value,
-            children,
-        } = resolver;
-        let ptr = &mut (*out).prefix;
-        ArchivedVec::resolve_from_slice(self.prefix(), pos + offset_from(out, ptr), prefix, ptr);
-        let ptr = &mut (*out).value;
-        self.value()
-            .cloned()
-            .resolve(pos + offset_from(out, ptr), value, ptr);
-        let ptr = &mut (*out).children;
-        self.children_arc()
-            .resolve(pos + offset_from(out, ptr), children, ptr);
+/// diff of the children of a node with a node b, where the prefix of b starting at bo is not empty
+///
+/// if flipped, the children are from the new tree and b is from the old tree.
+fn diff_children<K: TKey, V: TValue + PartialEq>(
+    path: &mut Vec<K>,
+    children: &[ArcRadixTree<K, V>],
+    b: &ArcRadixTree<K, V>,
+    bo: usize,
+    flipped: bool,
+    res: &mut Vec<Change<K, V>>,
+) {
+    let only_child = |path: &mut Vec<K>, child, res: &mut Vec<Change<K, V>>| {
+        if flipped {
+            insert_all(path, child, 0, res)
+        } else {
+            delete_all(path, child, 0, res)
+        }
+    };
+    let only_b = |path: &mut Vec<K>, res: &mut Vec<Change<K, V>>| {
+        if flipped {
+            delete_all(path, b, bo, res)
+        } else {
+            insert_all(path, b, bo, res)
+        }
+    };
+    let first = b.prefix()[bo];
+    let mut b_done = false;
+    for child in children {
+        let c = child.prefix()[0];
+        if !b_done && c >= first {
+            b_done = true;
+            if c == first {
+                if flipped {
+                    diff0(path, b, bo, child, 0, res);
+                } else {
+                    diff0(path, child, 0, b, bo, res);
+                }
+                continue;
+            }
+            only_b(path, res);
+        }
+        only_child(path, child, res);
+    }
+    if !b_done {
+        only_b(path, res);
     }
 }
 
-impl<S, K, V> Serialize<S> for ArcRadixTree<K, V>
-where
-    K: TKey + Serialize<S>,
-    V: TValue + Serialize<S>,
-    S: ScratchSpace + Serializer + SharedSerializeRegistry,
-{
-    fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
-        let prefix = rkyv::vec::ArchivedVec::serialize_from_slice(self.prefix(), serializer)?;
-        let value = self.value().cloned().serialize(serializer)?;
-        let arc = self.children_arc();
-        let arc: &Arc<Vec<ArcRadixTree<K, V>>> = unsafe { std::mem::transmute(arc) };
-        let children = arc.serialize(serializer)?;
-        Ok(ArcRadixTreeResolver {
-            prefix,
-            value,
-            children,
-        })
+/// A key that was changed differently on both sides of a [three_way_merge](ArcRadixTree::three_way_merge)
+///
+/// The values are given in the order base, ours, theirs, leaving out the values that do not exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict<K, V> {
+    /// both sides added the key with different values
+    BothAdded(K, V, V),
+    /// both sides changed the value of the key to different values
+    BothModified(K, V, V, V),
+    /// ours changed the value of the key, theirs removed it
+    ModifiedDeleted(K, V, V),
+    /// ours removed the key, theirs changed its value
+    DeletedModified(K, V, V),
+}
+
+impl<K: TKey, V: TValue + PartialEq> ArcRadixTree<K, V> {
+    /// merge the changes from base to ours and from base to theirs
+    ///
+    /// keys that were only changed on one side get the changed value. For keys that were changed
+    /// differently on both sides, `resolve` is called and the key is removed if it returns None.
+    ///
+    /// subtrees that one side shares with base are taken from the other side without visiting them,
+    /// so the cost is proportional to the size of the changes, not to the size of the trees.
+    pub fn three_way_merge(
+        base: &Self,
+        ours: &Self,
+        theirs: &Self,
+        resolve: impl Fn(MergeConflict<&[K], &V>) -> Option<V>,
+    ) -> Self {
+        merge0(
+            &mut Vec::new(),
+            [Some((base, 0)), Some((ours, 0)), Some((theirs, 0))],
+            &resolve,
+        )
     }
 }
 
-impl<D, K, V> Deserialize<ArcRadixTree<K, V>, D> for ArchivedArcRadixTree<K, V>
-where
-    D: SharedDeserializeRegistry,
-    K: TKey,
-    V: TValue,
-    Archived<K>: Deserialize<K, D>,
-    Archived<V>: Deserialize<V, D>,
-{
-    fn deserialize(&self, deserializer: &mut D) -> Result<ArcRadixTree<K, V>, D::Error> {
-        let prefix: Vec<K> = self.prefix.deserialize(deserializer)?;
-        let value: Option<V> = self.value.deserialize(deserializer)?;
-        let children: Arc<Vec<ArcRadixTree<K, V>>> = self.children.deserialize(deserializer)?;
-        Ok(ArcRadixTree {
-            prefix: Fragment::from(prefix.as_ref()),
-            value,
-            children,
+/// a node of one of the merged trees, with the prefix starting at an offset
+type Cursor<'a, K, V> = Option<(&'a ArcRadixTree<K, V>, usize)>;
+
+/// the value and children of a node if it ends at the given offset
+fn cursor_here<K: TKey, V: TValue>(
+    cursor: Cursor<'_, K, V>,
+    n: usize,
+) -> Option<&ArcRadixTree<K, V>> {
+    cursor
+        .filter(|(node, o)| o + n == node.prefix().len())
+        .map(|x| x.0)
+}
+
+/// the cursors for the children of the node at the given offset
+fn child_cursors<K: TKey, V: TValue>(
+    cursor: Cursor<'_, K, V>,
+    n: usize,
+) -> Vec<(&ArcRadixTree<K, V>, usize)> {
+    match (cursor_here(cursor, n), cursor) {
+        (Some(node), _) => node.children().iter().map(|c| (c, 0)).collect(),
+        (None, Some((node, o))) => vec![(node, o + n)],
+        (None, None) => Vec::new(),
+    }
+}
+
+/// true if two cursors are known to have the same content, without looking at the children
+fn same<K: TKey, V: TValue + PartialEq>(a: Cursor<K, V>, b: Cursor<K, V>) -> bool {
+    match (a, b) {
+        (Some((a, ao)), Some((b, bo))) => {
+            a.prefix()[ao..] == b.prefix()[bo..]
+                && a.value() == b.value()
+                && Arc::ptr_eq(a.children_arc(), b.children_arc())
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// the subtree of a cursor, sharing the children
+fn share<K: TKey, V: TValue>(cursor: Cursor<K, V>) -> ArcRadixTree<K, V> {
+    match cursor {
+        Some((node, 0)) => node.clone(),
+        Some((node, o)) => ArcRadixTree::from_arc(
+            node.prefix()[o..].into(),
+            node.value().cloned(),
+            node.children_arc().clone(),
+        ),
+        None => ArcRadixTree::default(),
+    }
+}
+
+fn merge_values<K: TKey, V: TValue + PartialEq>(
+    key: &[K],
+    base: Option<&V>,
+    ours: Option<&V>,
+    theirs: Option<&V>,
+    resolve: &impl Fn(MergeConflict<&[K], &V>) -> Option<V>,
+) -> Option<V> {
+    if ours == theirs || theirs == base {
+        ours.cloned()
+    } else if ours == base {
+        theirs.cloned()
+    } else {
+        resolve(match (base, ours, theirs) {
+            (None, Some(o), Some(t)) => MergeConflict::BothAdded(key, o, t),
+            (Some(b), Some(o), Some(t)) => MergeConflict::BothModified(key, b, o, t),
+            (Some(b), Some(o), None) => MergeConflict::ModifiedDeleted(key, b, o),
+            (Some(b), None, Some(t)) => MergeConflict::DeletedModified(key, b, t),
+            // all other cases have at least two equal values
+            _ => unreachable!(),
         })
     }
 }
 
-#[cfg(feature = "rkyv_validated")]
-mod validation_support {
-    use super::{TKey, TValue};
-    use bytecheck::CheckBytes;
-    use core::fmt;
+/// merge of base, ours and theirs at the same position
+fn merge0<K: TKey, V: TValue + PartialEq>(
+    key: &mut Vec<K>,
+    [base, ours, theirs]: [Cursor<K, V>; 3],
+    resolve: &impl Fn(MergeConflict<&[K], &V>) -> Option<V>,
+) -> ArcRadixTree<K, V> {
+    // this is where we skip subtrees that did not change on one side
+    if same(ours, theirs) || same(base, theirs) {
+        return share(ours);
+    }
+    if same(base, ours) {
+        return share(theirs);
+    }
+    let cursors = [base, ours, theirs];
+    let rests = cursors
+        .iter()
+        .flatten()
+        .map(|(node, o)| &node.prefix()[*o..])
+        .collect::<Vec<_>>();
+    // at least ours or theirs is not None, otherwise they would be the same
+    let n = rests[1..]
+        .iter()
+        .map(|rest| common_prefix(rests[0], rest))
+        .fold(rests[0].len(), |n, m| n.min(m));
+    let prefix = &rests[0][..n];
+    key.extend_from_slice(prefix);
+    let [base_here, ours_here, theirs_here] = [
+        cursor_here(base, n),
+        cursor_here(ours, n),
+        cursor_here(theirs, n),
+    ];
+    let value = merge_values(
+        key,
+        base_here.and_then(|x| x.value()),
+        ours_here.and_then(|x| x.value()),
+        theirs_here.and_then(|x| x.value()),
+        resolve,
+    );
+    let mut iters = [
+        child_cursors(base, n).into_iter().peekable(),
+        child_cursors(ours, n).into_iter().peekable(),
+        child_cursors(theirs, n).into_iter().peekable(),
+    ];
+    let mut merged = Vec::new();
+    loop {
+        let first = iters
+            .iter_mut()
+            .filter_map(|iter| iter.peek().map(|(node, o)| node.prefix()[*o]))
+            .min();
+        let first = match first {
+            Some(first) => first,
+            None => break,
+        };
+        let mut next = [None, None, None];
+        for (cursor, iter) in next.iter_mut().zip(iters.iter_mut()) {
+            *cursor = iter.next_if(|(node, o)| node.prefix()[*o] == first);
+        }
+        merged.push(merge0(key, next, resolve));
+    }
+    key.truncate(key.len() - n);
+    let mut res = ArcRadixTree::new(prefix.into(), value, merged);
+    res.unsplit();
+    res
+}
+
+#[cfg(feature = "merkle")]
+mod merkle_support {
+    use super::super::merkle::{node_hash, MerkleHash, StableHash};
+    use super::super::{common_prefix, AbstractRadixTree, TKey, TValue};
+    use super::ArcRadixTree;
+    use std::{cmp::Ordering, sync::Arc};
+
+    impl<K: TKey + StableHash, V: TValue + StableHash> ArcRadixTree<K, V> {
+        /// The content hash of this tree
+        ///
+        /// The hash is cached for every node and only computed again for the nodes on the path to a change.
+        /// Two trees have the same hash if and only if they have the same content, so once the hashes are
+        /// computed, comparing two trees is O(1).
+        pub fn merkle_hash(&self) -> &MerkleHash {
+            self.hash.get_or_init(|| {
+                node_hash(
+                    self.prefix(),
+                    self.value(),
+                    self.children().iter().map(Self::merkle_hash),
+                )
+            })
+        }
+    }
+
+    impl<K: TKey + StableHash, V: TValue + StableHash + PartialEq> ArcRadixTree<K, V> {
+        /// compute the prefixes where this tree and that tree differ, in key order
+        ///
+        /// each prefix is either a key that has a different value in the two trees, or the prefix of
+        /// a subtree that only exists in one of the trees. Subtrees with the same hash are skipped,
+        /// so only the paths to the differences are visited.
+        pub fn diff_prefixes(&self, that: &Self) -> Vec<Vec<K>> {
+            let mut res = Vec::new();
+            diff_prefixes0(&mut Vec::new(), self, 0, that, 0, &mut res);
+            res
+        }
+    }
+
+    /// report a subtree that only exists in one tree, with the prefix starting at offset
+    fn one_sided<K: TKey, V: TValue>(
+        path: &[K],
+        node: &ArcRadixTree<K, V>,
+        offset: usize,
+        res: &mut Vec<Vec<K>>,
+    ) {
+        // only the root can be empty
+        if !node.is_empty() {
+            let mut prefix = path.to_vec();
+            prefix.extend_from_slice(&node.prefix()[offset..]);
+            res.push(prefix);
+        }
+    }
+
+    /// like `diff0`, but collecting prefixes instead of changes
+    fn diff_prefixes0<K: TKey + StableHash, V: TValue + StableHash + PartialEq>(
+        path: &mut Vec<K>,
+        a: &ArcRadixTree<K, V>,
+        ao: usize,
+        b: &ArcRadixTree<K, V>,
+        bo: usize,
+        res: &mut Vec<Vec<K>>,
+    ) {
+        // hashes can only be compared if both nodes are complete
+        if ao == 0 && bo == 0 && a.merkle_hash() == b.merkle_hash() {
+            return;
+        }
+        let pa = &a.prefix()[ao..];
+        let pb = &b.prefix()[bo..];
+        let n = common_prefix(pa, pb);
+        let len = path.len();
+        if n == pa.len() && n == pb.len() {
+            path.extend_from_slice(pa);
+            if a.value() != b.value() {
+                res.push(path.clone());
+            }
+            if !Arc::ptr_eq(a.children_arc(), b.children_arc()) {
+                let (ac, bc) = (a.children(), b.children());
+                let (mut i, mut j) = (0, 0);
+                while i < ac.len() || j < bc.len() {
+                    let ordering = match (ac.get(i), bc.get(j)) {
+                        (Some(ca), Some(cb)) => ca.prefix()[0].cmp(&cb.prefix()[0]),
+                        (Some(_), None) => Ordering::Less,
+                        _ => Ordering::Greater,
+                    };
+                    match ordering {
+                        Ordering::Less => {
+                            one_sided(path, &ac[i], 0, res);
+                            i += 1;
+                        }
+                        Ordering::Greater => {
+                            one_sided(path, &bc[j], 0, res);
+                            j += 1;
+                        }
+                        Ordering::Equal => {
+                            diff_prefixes0(path, &ac[i], 0, &bc[j], 0, res);
+                            i += 1;
+                            j += 1;
+                        }
+                    }
+                }
+            }
+        } else if n == pa.len() {
+            // a ends within b, and b continues in the children of a
+            path.extend_from_slice(pa);
+            if a.value().is_some() {
+                res.push(path.clone());
+            }
+            diff_prefixes_children(path, a.children(), b, bo + n, res);
+        } else if n == pb.len() {
+            // b ends within a, and a continues in the children of b
+            path.extend_from_slice(pb);
+            if b.value().is_some() {
+                res.push(path.clone());
+            }
+            diff_prefixes_children(path, b.children(), a, ao + n, res);
+        } else if pa[n] < pb[n] {
+            one_sided(path, a, ao, res);
+            one_sided(path, b, bo, res);
+        } else {
+            one_sided(path, b, bo, res);
+            one_sided(path, a, ao, res);
+        }
+        path.truncate(len);
+    }
+
+    /// diff prefixes of the children of a node with a node b, where the prefix of b starting at bo is not empty
+    fn diff_prefixes_children<K: TKey + StableHash, V: TValue + StableHash + PartialEq>(
+        path: &mut Vec<K>,
+        children: &[ArcRadixTree<K, V>],
+        b: &ArcRadixTree<K, V>,
+        bo: usize,
+        res: &mut Vec<Vec<K>>,
+    ) {
+        let first = b.prefix()[bo];
+        let mut b_done = false;
+        for child in children {
+            let c = child.prefix()[0];
+            if !b_done && c >= first {
+                b_done = true;
+                if c == first {
+                    diff_prefixes0(path, child, 0, b, bo, res);
+                    continue;
+                }
+                one_sided(path, b, bo, res);
+            }
+            one_sided(path, child, 0, res);
+        }
+        if !b_done {
+            one_sided(path, b, bo, res);
+        }
+    }
+}
+
+#[cfg(feature = "rkyv")]
+mod rkyv_support {
+    use super::super::{eq0, offset_from, AbstractRadixTree, TKey, TValue};
+    use super::{ArcRadixTree, Fragment};
     use rkyv::{
-        validation::{ArchiveContext, SharedContext},
-        Archived,
+        de::SharedDeserializeRegistry,
+        ser::{ScratchSpace, Serializer, SharedSerializeRegistry},
+        vec::ArchivedVec,
+        Archive, Archived, Deserialize, Resolver, Serialize,
     };
+    use std::sync::Arc;
+
+    impl<K: TKey + Archive<Archived = K>, V: TValue + Archive<Archived = V>>
+        From<&ArchivedArcRadixTree<K, V>> for ArcRadixTree<K, V>
+    {
+        fn from(value: &ArchivedArcRadixTree<K, V>) -> Self {
+            let children = value.children().iter().map(Self::from).collect::<Vec<_>>();
+            let children = Arc::new(children);
+            ArcRadixTree::from_arc(value.prefix().into(), value.value().cloned(), children)
+        }
+    }
+
+    impl<K: TKey + Archive<Archived = K>, V: TValue + Archive<Archived = V>> AbstractRadixTree<K, V>
+        for ArchivedArcRadixTree<K, V>
+    {
+        type Materialized = ArcRadixTree<K, V>;
 
-    use super::ArchivedArcRadixTree;
+        fn prefix(&self) -> &[K] {
+            &self.prefix
+        }
+
+        fn value(&self) -> Option<&V> {
+            self.value.as_ref()
+        }
 
-    /// Validation error for a radix tree
-    #[derive(Debug)]
-    pub enum ArchivedRadixTreeError {
-        /// error with the prefix
-        Prefix,
-        /// error with the value
-        Value,
-        /// error with the children
-        Children(String),
-        /// error with the order of the children
-        Order,
+        fn children(&self) -> &[Self] {
+            &self.children
+        }
     }
 
-    impl std::error::Error for ArchivedRadixTreeError {}
+    impl_std_traits!([K: TKey + Archive<Archived = K>, V: TValue + Archive<Archived = V>] ArchivedArcRadixTree<K, V>);
 
-    impl std::fmt::Display for ArchivedRadixTreeError {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "{:?}", self)
+    impl<K, V> PartialEq<ArchivedArcRadixTree<K, V>> for ArcRadixTree<K, V>
+    where
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive<Archived = V> + PartialEq,
+    {
+        fn eq(&self, other: &ArchivedArcRadixTree<K, V>) -> bool {
+            eq0(self, other)
+        }
+    }
+
+    impl<K, V> PartialEq<ArcRadixTree<K, V>> for ArchivedArcRadixTree<K, V>
+    where
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive<Archived = V> + PartialEq,
+    {
+        fn eq(&self, other: &ArcRadixTree<K, V>) -> bool {
+            eq0(self, other)
         }
     }
-    impl<C, K, V> bytecheck::CheckBytes<C> for ArchivedArcRadixTree<K, V>
+
+    pub struct ArcRadixTreeResolver<K: TKey + Archive<Archived = K>, V: TValue + Archive> {
+        prefix: Resolver<Vec<K>>,
+        value: Resolver<Option<V>>,
+        children: Resolver<Arc<Vec<ArcRadixTree<K, V>>>>,
+    }
+
+    #[repr(C)]
+    pub struct ArchivedArcRadixTree<K, V>
     where
-        C: ?Sized + ArchiveContext + SharedContext,
-        C::Error: std::error::Error,
-        K: TKey,
-        V: TValue,
-        Archived<Vec<K>>: bytecheck::CheckBytes<C>,
-        Archived<Option<V>>: bytecheck::CheckBytes<C>,
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive,
     {
-        type Error = ArchivedRadixTreeError;
-        unsafe fn check_bytes<'a>(
-            this: *const Self,
-            context: &mut C,
-        ) -> Result<&'a Self, Self::Error> {
-            let Self {
+        prefix: Archived<Vec<K>>,
+        value: Archived<Option<V>>,
+        children: Archived<Arc<Vec<ArcRadixTree<K, V>>>>,
+    }
+
+    impl<K: TKey + Archive<Archived = K>, V: TValue + Archive> Archive for ArcRadixTree<K, V> {
+        type Archived = ArchivedArcRadixTree<K, V>;
+
+        type Resolver = ArcRadixTreeResolver<K, V>;
+
+        unsafe fn resolve(&self, pos: usize, resolver: Self::Resolver, out: *mut Self::Archived) {
+            let ArcRadixTreeResolver {
                 prefix,
                 value,
                 children,
-            } = &(*this);
-            // check the prefix
-            CheckBytes::check_bytes(prefix, context).map_err(|_| ArchivedRadixTreeError::Prefix)?;
-            // check the value, if present
-            CheckBytes::check_bytes(value, context).map_err(|_| ArchivedRadixTreeError::Value)?;
-            // check that the prefix of all children is of non zero length
-            if !children.iter().all(|child| !child.prefix.is_empty()) {
-                return Err(ArchivedRadixTreeError::Children(
-                    "empty child prefix".into(),
-                ));
-            };
-            // check the order of the children
-            if !children
-                .iter()
-                .zip(children.iter().skip(1))
-                .all(|(a, b)| a.prefix[0] < b.prefix[0])
-            {
-                return Err(ArchivedRadixTreeError::Order);
-            };
-            // recursively check the children
-            CheckBytes::check_bytes(children, context)
-                .map_err(|e| ArchivedRadixTreeError::Children(e.to_string()))?;
-
-            Ok(&*this)
+            } = resolver;
+            let ptr = &mut (*out).prefix;
+            ArchivedVec::resolve_from_slice(
+                self.prefix(),
+                pos + offset_from(out, ptr),
+                prefix,
+                ptr,
+            );
+            let ptr = &mut (*out).value;
+            self.value()
+                .cloned()
+                .resolve(pos + offset_from(out, ptr), value, ptr);
+            let ptr = &mut (*out).children;
+            self.children_arc()
+                .resolve(pos + offset_from(out, ptr), children, ptr);
+        }
+    }
+
+    impl<S, K, V> Serialize<S> for ArcRadixTree<K, V>
+    where
+        K: TKey + Archive<Archived = K> + Serialize<S>,
+        V: TValue + Serialize<S>,
+        S: ScratchSpace + Serializer + SharedSerializeRegistry,
+    {
+        fn serialize(&self, serializer: &mut S) -> Result<Self::Resolver, S::Error> {
+            let prefix = rkyv::vec::ArchivedVec::serialize_from_slice(self.prefix(), serializer)?;
+            let value = self.value().cloned().serialize(serializer)?;
+            let arc = self.children_arc();
+            let arc: &Arc<Vec<ArcRadixTree<K, V>>> = unsafe { std::mem::transmute(arc) };
+            let children = arc.serialize(serializer)?;
+            Ok(ArcRadixTreeResolver {
+                prefix,
+                value,
+                children,
+            })
+        }
+    }
+
+    impl<D, K, V> Deserialize<ArcRadixTree<K, V>, D> for ArchivedArcRadixTree<K, V>
+    where
+        D: SharedDeserializeRegistry,
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive,
+        Archived<K>: Deserialize<K, D>,
+        Archived<V>: Deserialize<V, D>,
+    {
+        fn deserialize(&self, deserializer: &mut D) -> Result<ArcRadixTree<K, V>, D::Error> {
+            let prefix: Vec<K> = self.prefix.deserialize(deserializer)?;
+            let value: Option<V> = self.value.deserialize(deserializer)?;
+            let children: Arc<Vec<ArcRadixTree<K, V>>> = self.children.deserialize(deserializer)?;
+            Ok(ArcRadixTree::from_arc(
+                Fragment::from(prefix.as_ref()),
+                value,
+                children,
+            ))
+        }
+    }
+
+    #[cfg(feature = "rkyv_validated")]
+    mod validation_support {
+        use super::{TKey, TValue};
+        use bytecheck::CheckBytes;
+        use core::fmt;
+        use rkyv::{
+            validation::{ArchiveContext, SharedContext},
+            Archive, Archived,
+        };
+
+        use super::ArchivedArcRadixTree;
+
+        /// Validation error for a radix tree
+        #[derive(Debug)]
+        pub enum ArchivedRadixTreeError {
+            /// error with the prefix
+            Prefix,
+            /// error with the value
+            Value,
+            /// error with the children
+            Children(String),
+            /// error with the order of the children
+            Order,
+        }
+
+        impl std::error::Error for ArchivedRadixTreeError {}
+
+        impl std::fmt::Display for ArchivedRadixTreeError {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:?}", self)
+            }
+        }
+        impl<C, K, V> bytecheck::CheckBytes<C> for ArchivedArcRadixTree<K, V>
+        where
+            C: ?Sized + ArchiveContext + SharedContext,
+            C::Error: std::error::Error,
+            K: TKey + Archive<Archived = K>,
+            V: TValue + Archive,
+            Archived<Vec<K>>: bytecheck::CheckBytes<C>,
+            Archived<Option<V>>: bytecheck::CheckBytes<C>,
+        {
+            type Error = ArchivedRadixTreeError;
+            unsafe fn check_bytes<'a>(
+                this: *const Self,
+                context: &mut C,
+            ) -> Result<&'a Self, Self::Error> {
+                let Self {
+                    prefix,
+                    value,
+                    children,
+                } = &(*this);
+                // check the prefix
+                CheckBytes::check_bytes(prefix, context)
+                    .map_err(|_| ArchivedRadixTreeError::Prefix)?;
+                // check the value, if present
+                CheckBytes::check_bytes(value, context)
+                    .map_err(|_| ArchivedRadixTreeError::Value)?;
+                // recursively check the children. This must come first, since it checks the pointer to
+                // the children, so they can be read below.
+                CheckBytes::check_bytes(children, context)
+                    .map_err(|e| ArchivedRadixTreeError::Children(e.to_string()))?;
+                // check that the prefix of all children is of non zero length
+                if !children.iter().all(|child| !child.prefix.is_empty()) {
+                    return Err(ArchivedRadixTreeError::Children(
+                        "empty child prefix".into(),
+                    ));
+                };
+                // check the order of the children
+                if !children
+                    .iter()
+                    .zip(children.iter().skip(1))
+                    .all(|(a, b)| a.prefix[0] < b.prefix[0])
+                {
+                    return Err(ArchivedRadixTreeError::Order);
+                };
+                Ok(&*this)
+            }
         }
     }
 }
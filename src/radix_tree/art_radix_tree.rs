@@ -0,0 +1,392 @@
+use super::internals;
+use internals::Fragment;
+use std::ops::Deref;
+
+use super::{AbstractRadixTree, RadixTree, TValue};
+
+/// prefixes up to this length are stored inline
+const INLINE: usize = 22;
+
+/// The prefix of a node, stored inline if it is short enough, so most nodes do not need an allocation for it
+#[derive(Clone)]
+enum Prefix {
+    Inline(u8, [u8; INLINE]),
+    Heap(Box<[u8]>),
+}
+
+impl Deref for Prefix {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Prefix::Inline(len, data) => &data[..*len as usize],
+            Prefix::Heap(data) => data,
+        }
+    }
+}
+
+impl From<&[u8]> for Prefix {
+    fn from(value: &[u8]) -> Self {
+        if value.len() <= INLINE {
+            let mut data = [0u8; INLINE];
+            data[..value.len()].copy_from_slice(value);
+            Prefix::Inline(value.len() as u8, data)
+        } else {
+            Prefix::Heap(value.into())
+        }
+    }
+}
+
+/// The children of an inner node, with an index from the first byte of a child to its position
+#[derive(Clone)]
+struct Inner<I, V> {
+    index: I,
+    children: Box<[ArtRadixTree<V>]>,
+}
+
+/// The children of a node, with a layout depending on the number of children, like the nodes of an
+/// adaptive radix tree.
+///
+/// The children themselves are always stored as a sorted slice, since that is what the generic algorithms
+/// work with. Only the index to look them up differs.
+#[derive(Clone)]
+enum Children<V> {
+    /// no children, no allocation
+    Leaf,
+    /// up to 4 children, first bytes searched linearly
+    Node4(Box<Inner<[u8; 4], V>>),
+    /// up to 16 children, first bytes searched linearly
+    Node16(Box<Inner<[u8; 16], V>>),
+    /// up to 48 children, table from the first byte to the position + 1, with 0 for no child
+    Node48(Box<Inner<[u8; 256], V>>),
+    /// more than 48 children, bitmap of the first bytes.
+    /// since the children are sorted, the position is the number of set bits below the first byte.
+    Node256(Box<Inner<[u64; 4], V>>),
+}
+
+impl<V> Children<V> {
+    fn new(mut children: Vec<ArtRadixTree<V>>) -> Self {
+        // empty children would be removed by unsplit anyway, and they have no first byte to index
+        children.retain(|child| !child.prefix.is_empty());
+        let n = children.len();
+        let children = children.into_boxed_slice();
+        let firsts = children.iter().map(|child| child.prefix[0]);
+        if n == 0 {
+            Children::Leaf
+        } else if n <= 4 {
+            let mut index = [0u8; 4];
+            for (key, first) in index.iter_mut().zip(firsts) {
+                *key = first;
+            }
+            Children::Node4(Box::new(Inner { index, children }))
+        } else if n <= 16 {
+            let mut index = [0u8; 16];
+            for (key, first) in index.iter_mut().zip(firsts) {
+                *key = first;
+            }
+            Children::Node16(Box::new(Inner { index, children }))
+        } else if n <= 48 {
+            let mut index = [0u8; 256];
+            for (i, first) in firsts.enumerate() {
+                index[first as usize] = (i + 1) as u8;
+            }
+            Children::Node48(Box::new(Inner { index, children }))
+        } else {
+            let mut index = [0u64; 4];
+            for first in firsts {
+                index[(first >> 6) as usize] |= 1 << (first & 63);
+            }
+            Children::Node256(Box::new(Inner { index, children }))
+        }
+    }
+
+    fn as_slice(&self) -> &[ArtRadixTree<V>] {
+        match self {
+            Children::Leaf => &[],
+            Children::Node4(node) => &node.children,
+            Children::Node16(node) => &node.children,
+            Children::Node48(node) => &node.children,
+            Children::Node256(node) => &node.children,
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [ArtRadixTree<V>] {
+        match self {
+            Children::Leaf => &mut [],
+            Children::Node4(node) => &mut node.children,
+            Children::Node16(node) => &mut node.children,
+            Children::Node48(node) => &mut node.children,
+            Children::Node256(node) => &mut node.children,
+        }
+    }
+
+    fn into_vec(self) -> Vec<ArtRadixTree<V>> {
+        match self {
+            Children::Leaf => Vec::new(),
+            Children::Node4(node) => node.children.into_vec(),
+            Children::Node16(node) => node.children.into_vec(),
+            Children::Node48(node) => node.children.into_vec(),
+            Children::Node256(node) => node.children.into_vec(),
+        }
+    }
+
+    fn position(&self, first: u8) -> Option<usize> {
+        match self {
+            Children::Leaf => None,
+            Children::Node4(node) => {
+                let n = node.children.len();
+                node.index[..n].iter().position(|key| *key == first)
+            }
+            Children::Node16(node) => {
+                let n = node.children.len();
+                node.index[..n].iter().position(|key| *key == first)
+            }
+            Children::Node48(node) => (node.index[first as usize] as usize).checked_sub(1),
+            Children::Node256(node) => {
+                let bits = &node.index;
+                let word = (first >> 6) as usize;
+                let bit = 1u64 << (first & 63);
+                if bits[word] & bit == 0 {
+                    return None;
+                }
+                let below = bits[..word].iter().map(|w| w.count_ones()).sum::<u32>();
+                Some((below + (bits[word] & (bit - 1)).count_ones()) as usize)
+            }
+        }
+    }
+}
+
+/// A radix tree with byte keys and adaptive nodes, like an adaptive radix tree
+///
+/// Depending on the number of children, a node looks up its children with a short linear scan over their
+/// first bytes (up to 4 or 16 children), a table from the first byte to the position (up to 48 children),
+/// or a bitmap of the first bytes (more than 48 children), instead of a binary search over the children.
+///
+/// Nodes are also smaller than in [RadixTree]. Nodes without children do not allocate, and short
+/// prefixes are stored inline. E.g. a node of an `ArtRadixTree<()>` is 48 bytes, a node of a
+/// `RadixTree<u8, ()>` 64 bytes.
+///
+/// Changing the children of a node rebuilds its layout, so this is best for trees that are looked up
+/// more often than they are changed. Modifying values in place, e.g. with
+/// [get_mut](super::AbstractRadixTreeMut::get_mut), does not.
+pub struct ArtRadixTree<V> {
+    prefix: Prefix,
+    value: Option<V>,
+    children: Children<V>,
+}
+
+impl<V: Clone> Clone for ArtRadixTree<V> {
+    fn clone(&self) -> Self {
+        Self {
+            prefix: self.prefix.clone(),
+            value: self.value.clone(),
+            children: self.children.clone(),
+        }
+    }
+}
+
+impl<V> Default for ArtRadixTree<V> {
+    fn default() -> Self {
+        Self {
+            prefix: Prefix::Inline(0, [0; INLINE]),
+            value: None,
+            children: Children::Leaf,
+        }
+    }
+}
+
+impl<V: TValue> AbstractRadixTree<u8, V> for ArtRadixTree<V> {
+    type Materialized = ArtRadixTree<V>;
+
+    fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    fn value(&self) -> Option<&V> {
+        self.value.as_ref()
+    }
+
+    fn children(&self) -> &[Self] {
+        self.children.as_slice()
+    }
+
+    fn child_index(&self, first: &u8) -> Option<usize> {
+        self.children.position(*first)
+    }
+}
+
+impl<V: TValue> internals::AbstractRadixTreeMut<u8, V> for ArtRadixTree<V> {
+    fn new(prefix: Fragment<u8>, value: Option<V>, children: Vec<Self>) -> Self {
+        Self {
+            prefix: Prefix::from(prefix.as_ref()),
+            value,
+            children: Children::new(children),
+        }
+    }
+
+    fn value_mut(&mut self) -> &mut Option<V> {
+        &mut self.value
+    }
+
+    // the index only depends on the first bytes of the children, so in place changes can keep it
+
+    fn children_mut(&mut self) -> &mut [Self] {
+        self.children.as_mut_slice()
+    }
+
+    fn edit_children<R>(&mut self, f: impl FnOnce(&mut Vec<Self>) -> R) -> R {
+        let mut children = std::mem::replace(&mut self.children, Children::Leaf).into_vec();
+        let res = f(&mut children);
+        self.children = Children::new(children);
+        res
+    }
+
+    fn set_prefix(&mut self, prefix: Fragment<u8>) {
+        self.prefix = Prefix::from(prefix.as_ref());
+    }
+
+    fn value_and_children_mut(&mut self) -> (&mut Option<V>, &mut [Self]) {
+        (&mut self.value, self.children.as_mut_slice())
+    }
+}
+
+impl_std_traits!(mut [V: TValue] ArtRadixTree<V>, u8);
+
+impl<V: TValue> From<RadixTree<u8, V>> for ArtRadixTree<V> {
+    fn from(value: RadixTree<u8, V>) -> Self {
+        let RadixTree {
+            prefix,
+            value,
+            children,
+        } = value;
+        let children = children.into_iter().map(Self::from).collect::<Vec<_>>();
+        internals::AbstractRadixTreeMut::new(prefix, value, children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AbstractRadixTree, ArtRadixTree, Children, RadixTree};
+    use crate::radix_tree::AbstractRadixTreeMut;
+    use quickcheck_macros::quickcheck;
+    use std::collections::BTreeMap;
+
+    fn kind<V>(tree: &ArtRadixTree<V>) -> usize {
+        match tree.children {
+            Children::Leaf => 0,
+            Children::Node4(_) => 4,
+            Children::Node16(_) => 16,
+            Children::Node48(_) => 48,
+            Children::Node256(_) => 256,
+        }
+    }
+
+    #[test]
+    fn adaptive_nodes() {
+        for (n, expected_kind) in [
+            (1, 4),
+            (4, 4),
+            (5, 16),
+            (16, 16),
+            (17, 48),
+            (48, 48),
+            (49, 256),
+            (128, 256),
+            (129, 256),
+            (256, 256),
+        ]
+        .iter()
+        .cloned()
+        {
+            // every other byte, so there are misses in between
+            let step = if n <= 128 { 2 } else { 1 };
+            let tree: ArtRadixTree<usize> = (0..n)
+                .map(|i| (vec![(i * step) as u8, 0], i))
+                .chain(Some((vec![], n)))
+                .collect();
+            assert_eq!(kind(&tree), expected_kind);
+            for b in 0..=255u8 {
+                let (i, rem) = (b as usize / step, b as usize % step);
+                let expected = if rem == 0 && i < n { Some(i) } else { None };
+                assert_eq!(tree.child_index(&b), expected);
+                assert_eq!(tree.get(&[b, 0]).cloned(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn node_size() {
+        use std::mem::size_of;
+        assert_eq!(size_of::<ArtRadixTree<()>>(), 48);
+        assert!(size_of::<ArtRadixTree<()>>() < size_of::<RadixTree<u8, ()>>());
+        assert!(size_of::<ArtRadixTree<u64>>() < size_of::<RadixTree<u8, u64>>());
+    }
+
+    #[test]
+    fn long_prefix() {
+        let key = (0..100u8).collect::<Vec<_>>();
+        let mut tree: ArtRadixTree<u8> = vec![(&key[..], 1), (&key[..10], 2)].into_iter().collect();
+        assert_eq!(tree.get(&key), Some(&1));
+        assert_eq!(tree.get(&key[..10]), Some(&2));
+        tree.remove(&key[..10]);
+        assert_eq!(tree.prefix(), &key[..]);
+        assert_eq!(tree.get(&key), Some(&1));
+    }
+
+    #[test]
+    fn layout_changes() {
+        let mut tree: ArtRadixTree<u8> = (0..20u8).map(|i| (vec![i * 2], i)).collect();
+        assert_eq!(kind(&tree), 48);
+        // changing values in place keeps the layout
+        let children = tree.children().as_ptr();
+        for v in tree.values_mut() {
+            *v += 1;
+        }
+        *tree.get_mut(&[2]).unwrap() = 100;
+        assert_eq!(tree.children().as_ptr(), children);
+        assert_eq!(tree.get(&[2]), Some(&100));
+        // changing the children does not
+        tree.insert(&[1], 200);
+        assert_eq!(tree.get(&[1]), Some(&200));
+        tree.remove_prefix(&[20]);
+        assert_eq!(tree.get(&[20]), None);
+        assert_eq!(tree.get(&[38]), Some(&20));
+        tree.retain(|k, _| k[0] < 5);
+        assert_eq!(kind(&tree), 4);
+        assert_eq!(tree.get(&[4]), Some(&3));
+        assert_eq!(tree.get(&[1]), Some(&200));
+    }
+
+    #[quickcheck]
+    fn art_same_as_flat(
+        a: BTreeMap<Vec<u8>, u8>,
+        b: BTreeMap<Vec<u8>, u8>,
+        keys: Vec<Vec<u8>>,
+    ) -> bool {
+        let mut art: ArtRadixTree<u8> = a.clone().into_iter().collect();
+        let mut reference = a;
+        let mut res = true;
+        for key in keys.iter().chain(b.keys()) {
+            res &= art.get(key) == reference.get(key);
+        }
+        art.outer_combine_with(
+            &b.clone().into_iter().collect::<ArtRadixTree<u8>>(),
+            |v, w| {
+                *v = *w;
+                true
+            },
+        );
+        reference.extend(b);
+        for key in keys.iter().chain(reference.keys()) {
+            res &= art.get(key) == reference.get(key);
+            res &= art.contains_key(key) == reference.contains_key(key);
+        }
+        for key in &keys {
+            res &= art.remove(key) == reference.remove(key);
+        }
+        res && art
+            .iter()
+            .map(|(k, v)| (k.to_vec(), *v))
+            .eq(reference.into_iter())
+    }
+}
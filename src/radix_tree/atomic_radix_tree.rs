@@ -0,0 +1,174 @@
+use super::{ArcRadixTree, TKey, TValue};
+use arc_swap::{ArcSwap, Guard};
+use std::{fmt, sync::Arc};
+
+/// a shared, immutable version of the tree
+type Snapshot<K, V> = Arc<ArcRadixTree<K, V>>;
+
+/// A handle to an [ArcRadixTree] that can be shared between threads
+///
+/// Readers get a consistent snapshot of the current tree without taking a lock, and writers publish a new tree
+/// atomically. Since an [ArcRadixTree] is copy on write, a writer usually takes a snapshot, modifies it and
+/// publishes the result, which shares all unchanged subtrees with the snapshot.
+///
+/// For multiple writers, [compare_and_publish](AtomicRadixTree::compare_and_publish) only publishes if the
+/// tree has not been replaced since the snapshot was taken, and [update](AtomicRadixTree::update) retries a
+/// change until it succeeds.
+pub struct AtomicRadixTree<K: TKey, V: TValue> {
+    current: ArcSwap<ArcRadixTree<K, V>>,
+}
+
+impl<K: TKey, V: TValue> AtomicRadixTree<K, V> {
+    /// Create a new handle with the given tree
+    pub fn new(tree: ArcRadixTree<K, V>) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(tree),
+        }
+    }
+
+    /// A snapshot of the current tree
+    ///
+    /// The snapshot is not affected by later changes, and does not prevent them.
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        self.current.load_full()
+    }
+
+    /// Replace the current tree, no matter what it is
+    pub fn publish(&self, tree: ArcRadixTree<K, V>) {
+        self.current.store(Arc::new(tree))
+    }
+
+    /// Replace the current tree if it is still the given snapshot
+    ///
+    /// Returns the published tree on success. Otherwise returns the current tree, which can be used
+    /// to try again.
+    pub fn compare_and_publish(
+        &self,
+        snapshot: &Snapshot<K, V>,
+        tree: ArcRadixTree<K, V>,
+    ) -> Result<Snapshot<K, V>, Snapshot<K, V>> {
+        let tree = Arc::new(tree);
+        let prev = self.current.compare_and_swap(snapshot, tree.clone());
+        if Arc::ptr_eq(&prev, snapshot) {
+            Ok(tree)
+        } else {
+            Err(Guard::into_inner(prev))
+        }
+    }
+
+    /// Apply a change to the current tree, retrying until no other writer gets in between
+    ///
+    /// `f` can be called multiple times, so it should not have side effects. Returns the published tree.
+    pub fn update(
+        &self,
+        mut f: impl FnMut(&ArcRadixTree<K, V>) -> ArcRadixTree<K, V>,
+    ) -> Snapshot<K, V> {
+        let mut snapshot = self.snapshot();
+        loop {
+            match self.compare_and_publish(&snapshot, f(&snapshot)) {
+                Ok(tree) => return tree,
+                Err(current) => snapshot = current,
+            }
+        }
+    }
+}
+
+impl<K: TKey, V: TValue> Default for AtomicRadixTree<K, V> {
+    fn default() -> Self {
+        Self::new(ArcRadixTree::default())
+    }
+}
+
+impl<K: TKey, V: TValue> From<ArcRadixTree<K, V>> for AtomicRadixTree<K, V> {
+    fn from(tree: ArcRadixTree<K, V>) -> Self {
+        Self::new(tree)
+    }
+}
+
+impl<K: TKey, V: TValue> fmt::Debug for AtomicRadixTree<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicRadixTree")
+            .field(&self.current.load())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArcRadixTree, AtomicRadixTree};
+    use crate::radix_tree::{AbstractRadixTree, AbstractRadixTreeMut};
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn snapshot_and_publish() {
+        let handle: AtomicRadixTree<u8, u32> = AtomicRadixTree::default();
+        let s0 = handle.snapshot();
+        let mut tree = (*s0).clone();
+        tree.insert(b"a", 1);
+        handle.publish(tree);
+        let s1 = handle.snapshot();
+        assert!(s0.is_empty());
+        assert_eq!(s1.get(b"a"), Some(&1));
+        // snapshots of an unchanged tree are the same
+        assert!(Arc::ptr_eq(&s1, &handle.snapshot()));
+    }
+
+    #[test]
+    fn compare_and_publish() {
+        let handle = AtomicRadixTree::new(ArcRadixTree::<u8, u32>::default());
+        let s0 = handle.snapshot();
+        let mut a = (*s0).clone();
+        a.insert(b"a", 1);
+        let mut b = (*s0).clone();
+        b.insert(b"b", 2);
+        let s1 = handle.compare_and_publish(&s0, a).unwrap();
+        // b was based on a stale snapshot
+        let current = handle.compare_and_publish(&s0, b).unwrap_err();
+        assert!(Arc::ptr_eq(&current, &s1));
+        assert_eq!(handle.snapshot().get(b"b"), None);
+    }
+
+    #[test]
+    fn concurrent_update() {
+        let handle = Arc::new(AtomicRadixTree::<u8, u32>::default());
+        let writers = (0..4u32)
+            .map(|t| {
+                let handle = handle.clone();
+                thread::spawn(move || {
+                    for i in 0..100u32 {
+                        let key = format!("{}/{}", t, i);
+                        handle.update(|tree| {
+                            let mut tree = tree.clone();
+                            tree.insert(key.as_bytes(), i);
+                            tree
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let reader = {
+            let handle = handle.clone();
+            thread::spawn(move || {
+                // every writer inserts its keys in order, so a snapshot never has a gap
+                let mut count = 0;
+                while count < 400 {
+                    let snapshot = handle.snapshot();
+                    let n = snapshot.iter().count();
+                    assert!(n >= count);
+                    for t in 0..4 {
+                        let keys = snapshot.scan_prefix(format!("{}/", t).as_bytes()).count();
+                        for i in 0..keys {
+                            assert!(snapshot.contains_key(format!("{}/{}", t, i).as_bytes()));
+                        }
+                    }
+                    count = n;
+                }
+            })
+        };
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        reader.join().unwrap();
+        assert_eq!(handle.snapshot().iter().count(), 400);
+    }
+}
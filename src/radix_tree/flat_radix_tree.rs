@@ -1,9 +1,7 @@
-use super::{internals, AbstractRadixTree, AbstractRadixTreeMut, Fragment, TKey, TValue};
-use std::fmt::Debug;
-use std::iter::FromIterator;
+use super::{internals, AbstractRadixTree, Fragment, TKey, TValue};
 
 /// A generic radix tree
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct RadixTree<K, V> {
     pub(crate) prefix: Fragment<K>,
     pub(crate) value: Option<V>,
@@ -26,15 +24,7 @@ impl<K: TKey, V: TValue> AbstractRadixTree<K, V> for RadixTree<K, V> {
     }
 }
 
-impl<E: TKey, K: AsRef<[E]>, V: TValue> FromIterator<(K, V)> for RadixTree<E, V> {
-    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
-        let mut res = RadixTree::default();
-        for (k, v) in iter.into_iter() {
-            res.insert(k.as_ref(), v)
-        }
-        res
-    }
-}
+impl_std_traits!(mut [K: TKey, V: TValue] RadixTree<K, V>);
 
 impl<K: TKey, V: TValue> internals::AbstractRadixTreeMut<K, V> for RadixTree<K, V> {
     fn new(prefix: Fragment<K>, value: Option<V>, children: Vec<Self>) -> Self {
@@ -49,12 +39,20 @@ impl<K: TKey, V: TValue> internals::AbstractRadixTreeMut<K, V> for RadixTree<K,
         &mut self.value
     }
 
-    fn children_mut(&mut self) -> &mut Vec<Self> {
+    fn children_mut(&mut self) -> &mut [Self] {
         &mut self.children
     }
 
-    fn prefix_mut(&mut self) -> &mut Fragment<K> {
-        &mut self.prefix
+    fn edit_children<R>(&mut self, f: impl FnOnce(&mut Vec<Self>) -> R) -> R {
+        f(&mut self.children)
+    }
+
+    fn set_prefix(&mut self, prefix: Fragment<K>) {
+        self.prefix = prefix;
+    }
+
+    fn value_and_children_mut(&mut self) -> (&mut Option<V>, &mut [Self]) {
+        (&mut self.value, &mut self.children)
     }
 }
 
@@ -70,7 +68,7 @@ impl<K: Clone, V> Default for RadixTree<K, V> {
 
 #[cfg(feature = "rkyv")]
 mod rkyv_support {
-    use super::super::{internals, offset_from};
+    use super::super::{eq0, internals, offset_from};
     use super::{AbstractRadixTree, Fragment, RadixTree, TKey, TValue};
     use internals::AbstractRadixTreeMut as _;
     use rkyv::{
@@ -80,25 +78,41 @@ mod rkyv_support {
     };
 
     #[repr(C)]
-    pub struct ArchivedRadixTree<K: TKey, V: TValue> {
+    pub struct ArchivedRadixTree<K: TKey + Archive<Archived = K>, V: TValue + Archive> {
         prefix: Archived<Vec<K>>,
         value: Archived<Option<V>>,
         children: Archived<Vec<RadixTree<K, V>>>,
     }
 
-    impl<K: TKey, V: TValue> std::fmt::Debug for ArchivedRadixTree<K, V> {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            f.debug_struct("ArchivedRadixTree").finish_non_exhaustive()
+    impl_std_traits!([K: TKey + Archive<Archived = K>, V: TValue + Archive<Archived = V>] ArchivedRadixTree<K, V>);
+
+    impl<K, V> PartialEq<ArchivedRadixTree<K, V>> for RadixTree<K, V>
+    where
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive<Archived = V> + PartialEq,
+    {
+        fn eq(&self, other: &ArchivedRadixTree<K, V>) -> bool {
+            eq0(self, other)
         }
     }
 
-    pub struct RadixTreeResolver<K: TKey, V: TValue> {
+    impl<K, V> PartialEq<RadixTree<K, V>> for ArchivedRadixTree<K, V>
+    where
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive<Archived = V> + PartialEq,
+    {
+        fn eq(&self, other: &RadixTree<K, V>) -> bool {
+            eq0(self, other)
+        }
+    }
+
+    pub struct RadixTreeResolver<K: TKey + Archive<Archived = K>, V: TValue + Archive> {
         prefix: Resolver<Vec<K>>,
         value: Resolver<Option<V>>,
         children: Resolver<Vec<RadixTree<K, V>>>,
     }
 
-    impl<K: TKey, V: TValue + Archive<Archived = V>> AbstractRadixTree<K, V>
+    impl<K: TKey + Archive<Archived = K>, V: TValue + Archive<Archived = V>> AbstractRadixTree<K, V>
         for ArchivedRadixTree<K, V>
     {
         fn prefix(&self) -> &[K] {
@@ -118,7 +132,7 @@ mod rkyv_support {
 
     impl<K, V> Archive for RadixTree<K, V>
     where
-        K: TKey + Archive,
+        K: TKey + Archive<Archived = K>,
         V: TValue + Archive,
     {
         type Archived = ArchivedRadixTree<K, V>;
@@ -154,7 +168,7 @@ mod rkyv_support {
 
     impl<S, K, V> Serialize<S> for RadixTree<K, V>
     where
-        K: TKey + Serialize<S>,
+        K: TKey + Archive<Archived = K> + Serialize<S>,
         V: TValue + Serialize<S>,
         S: ScratchSpace + Serializer,
     {
@@ -173,8 +187,8 @@ mod rkyv_support {
     impl<D, K, V> Deserialize<RadixTree<K, V>, D> for ArchivedRadixTree<K, V>
     where
         D: Fallible + ?Sized,
-        K: TKey,
-        V: TValue,
+        K: TKey + Archive<Archived = K>,
+        V: TValue + Archive,
         Archived<K>: Deserialize<K, D>,
         Archived<V>: Deserialize<V, D>,
     {
@@ -195,7 +209,7 @@ mod rkyv_support {
         use super::{TKey, TValue};
         use bytecheck::CheckBytes;
         use core::fmt;
-        use rkyv::{validation::ArchiveContext, Archived};
+        use rkyv::{validation::ArchiveContext, Archive, Archived};
 
         use super::ArchivedRadixTree;
 
@@ -223,8 +237,8 @@ mod rkyv_support {
         where
             C: ?Sized + ArchiveContext,
             C::Error: std::error::Error,
-            K: TKey,
-            V: TValue,
+            K: TKey + Archive<Archived = K>,
+            V: TValue + Archive,
             Archived<Vec<K>>: bytecheck::CheckBytes<C>,
             Archived<Option<V>>: bytecheck::CheckBytes<C>,
         {
@@ -269,12 +283,13 @@ mod rkyv_support {
 #[cfg(feature = "rkyv")]
 #[cfg(test)]
 mod tests {
-    use super::{AbstractRadixTree, AbstractRadixTreeMut, RadixTree};
+    use super::super::AbstractRadixTreeMut;
+    use super::{AbstractRadixTree, RadixTree};
 
     fn mk_string(n: usize) -> String {
         let text = n.to_string();
         text.chars()
-            .flat_map(|c| std::iter::repeat(c).take(100))
+            .flat_map(|c| std::iter::repeat_n(c, 100))
             .collect::<String>()
     }
 
@@ -304,4 +319,72 @@ mod tests {
         let _result: RadixTree<u8, ()> = archived.deserialize(&mut Infallible).unwrap();
         // println!("{:#?}", result);
     }
+
+    #[test]
+    fn archived_longest_prefix_match() {
+        let mut a = RadixTree::empty();
+        for i in 0..100 {
+            a.insert(i.to_string().as_bytes(), i);
+        }
+        use rkyv::*;
+        use ser::Serializer;
+        let mut serializer = ser::serializers::AllocSerializer::<256>::default();
+        serializer.serialize_value(&a).unwrap();
+        let bytes = serializer.into_serializer().into_inner();
+        let archived = unsafe { rkyv::archived_root::<RadixTree<u8, i32>>(&bytes) };
+        assert_eq!(archived.longest_prefix_match(b"1234"), Some((2, &12)));
+        assert_eq!(
+            archived.longest_prefix_match(b"1234"),
+            a.longest_prefix_match(b"1234")
+        );
+        assert_eq!(archived.prefixes_of(b"99x").count(), 2);
+    }
+
+    #[test]
+    fn archived_std_traits() {
+        use rkyv::ser::{serializers::AllocSerializer, Serializer};
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+        let hash = |x: &dyn Fn(&mut DefaultHasher)| {
+            let mut hasher = DefaultHasher::new();
+            x(&mut hasher);
+            hasher.finish()
+        };
+        let a: RadixTree<u8, u32> = (0..100u32).map(|i| (i.to_string(), i)).collect();
+        let mut serializer = AllocSerializer::<256>::default();
+        serializer.serialize_value(&a).unwrap();
+        let bytes = serializer.into_serializer().into_inner();
+        let archived = unsafe { rkyv::archived_root::<RadixTree<u8, u32>>(&bytes) };
+        assert!(a == *archived);
+        assert!(*archived == a);
+        assert!(*archived == *archived);
+        assert_eq!(hash(&|h| a.hash(h)), hash(&|h| archived.hash(h)));
+        assert_eq!(archived.into_iter().count(), 100);
+        assert!(format!("{:?}", archived).starts_with("ArchivedRadixTree {"));
+        let mut b = a.clone();
+        b.insert(b"1", 0);
+        assert!(b != *archived);
+    }
+
+    #[test]
+    fn archived_fuzzy_search() {
+        use rkyv::ser::{serializers::AllocSerializer, Serializer};
+        let a: RadixTree<u8, u32> = (0..1000u32).map(|i| (i.to_string(), i)).collect();
+        let mut serializer = AllocSerializer::<256>::default();
+        serializer.serialize_value(&a).unwrap();
+        let bytes = serializer.into_serializer().into_inner();
+        let archived = unsafe { rkyv::archived_root::<RadixTree<u8, u32>>(&bytes) };
+        let expected = a
+            .fuzzy_search(b"123", 1)
+            .map(|(k, v, d)| (k.to_vec(), *v, d))
+            .collect::<Vec<_>>();
+        let actual = archived
+            .fuzzy_search(b"123", 1)
+            .map(|(k, v, d)| (k.to_vec(), *v, d))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, actual);
+        assert!(actual.contains(&(b"123".to_vec(), 123, 0)));
+        assert!(actual.contains(&(b"23".to_vec(), 23, 1)));
+        assert!(actual.contains(&(b"124".to_vec(), 124, 1)));
+    }
 }
@@ -1,4 +1,4 @@
-use super::{internals, location, offset_from, AbstractRadixTree, Fragment, RadixTree, TKey};
+use super::{eq0, internals, location, offset_from, AbstractRadixTree, Fragment, RadixTree};
 use rkyv::{
     ser::{ScratchSpace, Serializer, SharedSerializeRegistry},
     vec::ArchivedVec,
@@ -6,6 +6,10 @@ use rkyv::{
 };
 use std::{collections::BTreeMap, sync::Arc};
 
+pub trait TKey: super::TKey + Archive<Archived = Self> {}
+
+impl<T: super::TKey + Archive<Archived = Self>> TKey for T {}
+
 pub trait TValue: Debug + Clone + Archive<Archived = Self> + Send + Sync + 'static {}
 
 impl<T: Debug + Clone + Archive<Archived = Self> + Send + Sync + 'static> TValue for T {}
@@ -69,15 +73,26 @@ impl<'a, K: TKey, V: TValue> internals::AbstractRadixTreeMut<K, V> for LazyRadix
         &mut self.value
     }
 
-    fn prefix_mut(&mut self) -> &mut Fragment<K> {
-        &mut self.prefix
+    fn set_prefix(&mut self, prefix: Fragment<K>) {
+        self.prefix = prefix;
+    }
+
+    fn children_mut(&mut self) -> &mut [Self] {
+        Arc::make_mut(self.children_arc_mut()).as_mut_slice()
     }
 
-    fn children_mut(&mut self) -> &mut Vec<Self> {
-        Arc::make_mut(self.children_arc_mut())
+    fn edit_children<R>(&mut self, f: impl FnOnce(&mut Vec<Self>) -> R) -> R {
+        f(Arc::make_mut(self.children_arc_mut()))
+    }
+
+    fn value_and_children_mut(&mut self) -> (&mut Option<V>, &mut [Self]) {
+        let children = self.children.get_or_create_mut(materialize_shallow);
+        (&mut self.value, Arc::make_mut(children).as_mut_slice())
     }
 }
 
+impl_std_traits!(mut ['a, K: TKey, V: TValue] LazyRadixTree<'a, K, V>);
+
 impl<K: TKey, V: TValue> From<RadixTree<K, V>> for LazyRadixTree<'static, K, V> {
     fn from(value: RadixTree<K, V>) -> Self {
         let RadixTree {
@@ -149,9 +164,27 @@ impl<K: TKey, V: TValue> AbstractRadixTree<K, V> for ArchivedLazyRadixTree<K, V>
     }
 }
 
+impl_std_traits!([K: TKey, V: TValue] ArchivedLazyRadixTree<K, V>);
+
+impl<'a, K: TKey, V: TValue + PartialEq> PartialEq<ArchivedLazyRadixTree<K, V>>
+    for LazyRadixTree<'a, K, V>
+{
+    fn eq(&self, other: &ArchivedLazyRadixTree<K, V>) -> bool {
+        eq0(self, other)
+    }
+}
+
+impl<'a, K: TKey, V: TValue + PartialEq> PartialEq<LazyRadixTree<'a, K, V>>
+    for ArchivedLazyRadixTree<K, V>
+{
+    fn eq(&self, other: &LazyRadixTree<'a, K, V>) -> bool {
+        eq0(self, other)
+    }
+}
+
 fn materialize_shallow<K: TKey, V: TValue>(
     children: &[ArchivedLazyRadixTree<K, V>],
-) -> Arc<Vec<LazyRadixTree<K, V>>> {
+) -> Arc<Vec<LazyRadixTree<'_, K, V>>> {
     Arc::new(
         children
             .iter()
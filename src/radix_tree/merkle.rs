@@ -0,0 +1,185 @@
+//! Content hashes for radix trees
+//!
+//! The hash of a node is the SHA-256 of its prefix, its value and the hashes of its children. Everything is
+//! encoded in a platform independent way, so hashes can be persisted and compared between machines.
+use super::{AbstractRadixTree, TKey, TValue};
+use sha2::{Digest, Sha256};
+use std::{rc::Rc, sync::Arc};
+
+/// A content hash of a radix tree
+pub type MerkleHash = [u8; 32];
+
+/// Hasher that is used by [StableHash]
+pub struct StableHasher(Sha256);
+
+impl StableHasher {
+    fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    /// Add bytes to the hash
+    pub fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes)
+    }
+
+    /// Add a length or count to the hash, as a little endian u64
+    pub fn write_len(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes())
+    }
+
+    fn finish(self) -> MerkleHash {
+        self.0.finalize().into()
+    }
+}
+
+/// A hash that is the same on all platforms, unlike [Hash](std::hash::Hash)
+///
+/// Needed for the keys and values of trees whose content hash is computed.
+/// Implementations must write enough to make the encoding unambiguous, e.g. prefix variable length data with its length.
+pub trait StableHash {
+    /// Add self to the hasher
+    fn stable_hash(&self, hasher: &mut StableHasher);
+}
+
+macro_rules! stable_hash_le {
+    ($($t:ty),*) => {
+        $(
+            impl StableHash for $t {
+                fn stable_hash(&self, hasher: &mut StableHasher) {
+                    hasher.write(&self.to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+stable_hash_le!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl StableHash for usize {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        (*self as u64).stable_hash(hasher)
+    }
+}
+
+impl StableHash for isize {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        (*self as i64).stable_hash(hasher)
+    }
+}
+
+impl StableHash for bool {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        (*self as u8).stable_hash(hasher)
+    }
+}
+
+impl StableHash for char {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        (*self as u32).stable_hash(hasher)
+    }
+}
+
+impl StableHash for () {
+    fn stable_hash(&self, _: &mut StableHasher) {}
+}
+
+impl StableHash for str {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_len(self.len());
+        hasher.write(self.as_bytes())
+    }
+}
+
+impl StableHash for String {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        self.as_str().stable_hash(hasher)
+    }
+}
+
+impl<T: StableHash> StableHash for [T] {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        hasher.write_len(self.len());
+        for x in self {
+            x.stable_hash(hasher)
+        }
+    }
+}
+
+impl<T: StableHash> StableHash for Vec<T> {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        self.as_slice().stable_hash(hasher)
+    }
+}
+
+impl<T: StableHash> StableHash for Option<T> {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        match self {
+            Some(x) => {
+                hasher.write(&[1]);
+                x.stable_hash(hasher)
+            }
+            None => hasher.write(&[0]),
+        }
+    }
+}
+
+impl<A: StableHash, B: StableHash> StableHash for (A, B) {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        self.0.stable_hash(hasher);
+        self.1.stable_hash(hasher)
+    }
+}
+
+impl<T: StableHash + ?Sized> StableHash for &T {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        (*self).stable_hash(hasher)
+    }
+}
+
+impl<T: StableHash + ?Sized> StableHash for Box<T> {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        self.as_ref().stable_hash(hasher)
+    }
+}
+
+impl<T: StableHash + ?Sized> StableHash for Rc<T> {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        self.as_ref().stable_hash(hasher)
+    }
+}
+
+impl<T: StableHash + ?Sized> StableHash for Arc<T> {
+    fn stable_hash(&self, hasher: &mut StableHasher) {
+        self.as_ref().stable_hash(hasher)
+    }
+}
+
+/// The hash of a node, given the hashes of its children
+pub(crate) fn node_hash<'a, K: StableHash, V: StableHash>(
+    prefix: &[K],
+    value: Option<&V>,
+    children: impl ExactSizeIterator<Item = &'a MerkleHash>,
+) -> MerkleHash {
+    let mut hasher = StableHasher::new();
+    prefix.stable_hash(&mut hasher);
+    value.stable_hash(&mut hasher);
+    hasher.write_len(children.len());
+    for child in children {
+        hasher.write(child);
+    }
+    hasher.finish()
+}
+
+/// The content hash of any flavour of radix tree, computed from scratch
+///
+/// [ArcRadixTree](super::ArcRadixTree) caches the hash of each node, see
+/// [merkle_hash](super::ArcRadixTree::merkle_hash).
+pub fn merkle_hash<K, V, T>(tree: &T) -> MerkleHash
+where
+    K: TKey + StableHash,
+    V: TValue + StableHash,
+    T: AbstractRadixTree<K, V>,
+{
+    let children = tree.children().iter().map(merkle_hash).collect::<Vec<_>>();
+    node_hash(tree.prefix(), tree.value(), children.iter())
+}
@@ -7,46 +7,180 @@
 //!
 //! Radix trees allow very quick (O(log n)) filtering by prefix, as well as very fast (O(1)) prepending a prefix.
 //!
-//! Radix trees in this crate come in three flavours:
+//! Radix trees in this crate come in five flavours:
 //! - [RadixTree](RadixTree) is the most straightforward flavour. It does not contain any indirection.
 //!   use this for short lived objects.
 //! - [ArcRadixTree](ArcRadixTree) allows cheap snapshots and has copy on write semantics.
 //!   use this for a longer lived in memory tree that evolves over time
 //! - [LazyRadixTree](LazyRadixTree) allows cheap snapshots, copy on write semantics, and lazy loading.
 //!   use this for e.g. memory mapping a giant radix tree from a large file, that does not fit in memory.
+//!   this flavour is only available with the `lazy_radixtree` feature.
+//! - [SummaryRadixTree](SummaryRadixTree) caches a summary of the values of each subtree, computed with a [Monoid].
+//!   use this if you need e.g. counts or sums of values by prefix, or positional access with `nth` and `rank`.
+//! - [ArtRadixTree](ArtRadixTree) is a tree with byte keys and adaptive nodes, like an adaptive radix tree.
+//!   use this for lookup heavy workloads, it is faster to look up and smaller than a [RadixTree](RadixTree).
+//!
+//! Archiving trees with rkyv is enabled by the `rkyv` feature. Without it, any `Debug + Clone + Send + Sync` type can be used as a value.
 //!
 //! No attempt is made to hide the internal structure. E.g. if you want to use a RadixTree as a set,
 //! this is possible by using unit as value type, but probably not very convenient.
-use std::{borrow::Borrow, cmp::Ordering, fmt::Debug, marker::PhantomData, ops::Deref, sync::Arc};
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    ops::{Bound, Deref, RangeBounds},
+    sync::Arc,
+};
 
 /// Trait for everything that is needed for a component to be a radix tree key component
-pub trait TKey: Debug + Ord + Copy + Archive<Archived = Self> + Send + Sync + 'static {}
+///
+/// Archiving a tree with rkyv additionally requires `K: Archive<Archived = K>`.
+pub trait TKey: Debug + Ord + Copy + Send + Sync + 'static {}
 
-impl<T: Debug + Ord + Copy + Archive<Archived = T> + Send + Sync + 'static> TKey for T {}
+impl<T: Debug + Ord + Copy + Send + Sync + 'static> TKey for T {}
 
 /// Trait for everything that is needed for a component to be a radix tree value
-pub trait TValue: Debug + Clone + Archive + Send + Sync + 'static {}
+///
+/// Archiving a tree with rkyv additionally requires `V: Archive`.
+pub trait TValue: Debug + Clone + Send + Sync + 'static {}
+
+impl<T: Debug + Clone + Send + Sync + 'static> TValue for T {}
+
+/// Implements the std traits that are the same for all flavours of radix tree and their archived forms.
+///
+/// Equality, ordering and hashing only depend on the mappings, so they are consistent across flavours.
+/// The key type is `K` unless given explicitly.
+/// The `mut` form additionally implements [FromIterator] and [Extend] for flavours that can be built.
+macro_rules! impl_std_traits {
+    (mut [$($g:tt)*] $t:ty) => {
+        impl_std_traits!(mut [$($g)*] $t, K);
+    };
+    (mut [$($g:tt)*] $t:ty, $k:ty) => {
+        impl_std_traits!([$($g)*] $t, $k);
+
+        impl<$($g)*, Q: AsRef<[$k]>> std::iter::FromIterator<(Q, V)> for $t {
+            fn from_iter<I: IntoIterator<Item = (Q, V)>>(iter: I) -> Self {
+                $crate::radix_tree::AbstractRadixTreeMut::from_unsorted_iter(
+                    iter,
+                    $crate::dedup::Keep::Last,
+                )
+            }
+        }
+
+        impl<$($g)*, Q: AsRef<[$k]>> Extend<(Q, V)> for $t {
+            fn extend<I: IntoIterator<Item = (Q, V)>>(&mut self, iter: I) {
+                use $crate::radix_tree::AbstractRadixTreeMut;
+                let that = Self::from_unsorted_iter(iter, $crate::dedup::Keep::Last);
+                self.outer_combine_with(&that, |v, w| {
+                    *v = w.clone();
+                    true
+                });
+            }
+        }
+    };
+    ([$($g:tt)*] $t:ty) => {
+        impl_std_traits!([$($g)*] $t, K);
+    };
+    ([$($g:tt)*] $t:ty, $k:ty) => {
+        impl<$($g)*> std::fmt::Debug for $t {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                $crate::radix_tree::debug0(self, stringify!($t), f)
+            }
+        }
+
+        impl<$($g)*> PartialEq for $t
+        where
+            V: PartialEq,
+        {
+            fn eq(&self, other: &Self) -> bool {
+                $crate::radix_tree::eq0(self, other)
+            }
+        }
+
+        impl<$($g)*> Eq for $t where V: Eq {}
+
+        impl<$($g)*> PartialOrd for $t
+        where
+            V: PartialOrd,
+        {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                use $crate::radix_tree::AbstractRadixTree;
+                self.iter().partial_cmp(other.iter())
+            }
+        }
+
+        impl<$($g)*> Ord for $t
+        where
+            V: Ord,
+        {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                use $crate::radix_tree::AbstractRadixTree;
+                self.iter().cmp(other.iter())
+            }
+        }
+
+        impl<$($g)*> std::hash::Hash for $t
+        where
+            $k: std::hash::Hash,
+            V: std::hash::Hash,
+        {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                $crate::radix_tree::hash0(self, state)
+            }
+        }
+
+        impl<'x, $($g)*> IntoIterator for &'x $t {
+            type Item = ($crate::radix_tree::IterKey<$k>, &'x V);
+            type IntoIter = $crate::radix_tree::Iter<'x, $k, V, $t>;
 
-impl<T: Debug + Clone + Archive + Send + Sync + 'static> TValue for T {}
+            fn into_iter(self) -> Self::IntoIter {
+                $crate::radix_tree::AbstractRadixTree::iter(self)
+            }
+        }
+    };
+}
 
-use rkyv::Archive;
 #[cfg(feature = "lazy_radixtree")]
 mod lazy_radix_tree;
 #[cfg(feature = "lazy_radixtree")]
 pub use lazy_radix_tree::LazyRadixTree;
-#[cfg(feature = "rkyv")]
 mod arc_radix_tree;
-#[cfg(feature = "rkyv")]
-pub use arc_radix_tree::ArcRadixTree;
+pub use arc_radix_tree::{ArcRadixTree, Change, MergeConflict};
+#[cfg(feature = "atomic_radixtree")]
+mod atomic_radix_tree;
+#[cfg(feature = "atomic_radixtree")]
+pub use atomic_radix_tree::AtomicRadixTree;
 use smallvec::SmallVec;
 use sorted_iter::sorted_pair_iterator::SortedByKey;
 mod flat_radix_tree;
+mod pattern;
+pub use pattern::{Pattern, PatternElement};
+mod art_radix_tree;
+mod summary_radix_tree;
+pub use art_radix_tree::ArtRadixTree;
+pub use summary_radix_tree::{Count, CountingMonoid, Max, Min, Monoid, Sum, SummaryRadixTree};
+#[cfg(feature = "merkle")]
+mod merkle;
+#[cfg(feature = "merkle")]
+pub use merkle::{merkle_hash, MerkleHash, StableHash, StableHasher};
+#[cfg(feature = "rayon")]
+mod rayon_support;
+#[cfg(feature = "serde")]
+mod serde_support;
+use crate::dedup::{sort_dedup_by, Keep};
 use crate::merge_state::{
     BoolOpMergeState, Converter, InPlaceVecMergeStateRef, MergeStateMut, MutateInput, NoConverter,
     VecMergeState,
 };
+use crate::OuterJoinArg;
 use binary_merge::MergeOperation;
 pub use flat_radix_tree::RadixTree;
+#[cfg(feature = "serde")]
+pub use serde_support::shared;
+#[cfg(feature = "serde")]
+pub use serde_support::structural;
 
 // common prefix of two slices.
 fn common_prefix<'a, T: Eq>(a: &'a [T], b: &'a [T]) -> usize {
@@ -57,7 +191,7 @@ pub(crate) mod internals {
     use super::*;
 
     /// A path fragment
-    #[derive(Debug, Clone, PartialEq, Eq)]
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     pub struct Fragment<T>(SmallVec<[T; 16]>);
 
     impl<T> AsRef<[T]> for Fragment<T> {
@@ -122,8 +256,15 @@ pub(crate) mod internals {
         /// for intermediate states, it must not be publicly exposed.
         fn new(prefix: Fragment<K>, value: Option<V>, children: Vec<Self>) -> Self;
         fn value_mut(&mut self) -> &mut Option<V>;
-        fn children_mut(&mut self) -> &mut Vec<Self>;
-        fn prefix_mut(&mut self) -> &mut Fragment<K>;
+        /// mutable access to the children, to modify them in place
+        ///
+        /// the first key of each child must stay the same, unless the child becomes empty.
+        fn children_mut(&mut self) -> &mut [Self];
+        /// change the set of children, e.g. by adding or removing children
+        fn edit_children<R>(&mut self, f: impl FnOnce(&mut Vec<Self>) -> R) -> R;
+        fn set_prefix(&mut self, prefix: Fragment<K>);
+        /// mutable access to value and children at the same time
+        fn value_and_children_mut(&mut self) -> (&mut Option<V>, &mut [Self]);
 
         /// create an artificial split at offset n
         /// splitting at n >= prefix.len() is an error
@@ -131,28 +272,27 @@ pub(crate) mod internals {
             assert!(n < self.prefix().len());
             let first = self.prefix()[..n].into();
             let rest = self.prefix()[n..].into();
-            let mut split = Self::new(first, None, Vec::new());
-            std::mem::swap(self, &mut split);
-            let mut child = split;
-            *child.prefix_mut() = rest;
-            self.children_mut().push(child);
+            let mut child = std::mem::take(self);
+            child.set_prefix(rest);
+            *self = Self::new(first, None, vec![child]);
         }
 
         /// removes degenerate node again
         fn unsplit(&mut self) {
             // remove all empty children
-            // this might sometimes not be necessary, but it is tricky to find out when.
-            self.children_mut().retain(|x| !x.is_empty());
+            if self.children().iter().any(|x| x.is_empty()) {
+                self.edit_children(|children| children.retain(|x| !x.is_empty()));
+            }
             // a single child and no own value is degenerate
             if self.children().len() == 1 && self.value().is_none() {
-                let mut child = self.children_mut().pop().unwrap();
+                let mut child = self.edit_children(|children| children.pop().unwrap());
                 child.prepend0(self.prefix());
                 *self = child;
             }
             // canonicalize prefix for empty node
             // this might sometimes not be necessary, but it is tricky to find out when.
             if self.is_empty() {
-                *self.prefix_mut() = Fragment::default();
+                self.set_prefix(Fragment::default());
             }
         }
 
@@ -161,7 +301,7 @@ pub(crate) mod internals {
                 let mut prefix1 = SmallVec::new();
                 prefix1.extend_from_slice(prefix);
                 prefix1.extend_from_slice(self.prefix());
-                *self.prefix_mut() = prefix1.into();
+                self.set_prefix(prefix1.into());
             }
         }
 
@@ -170,12 +310,14 @@ pub(crate) mod internals {
             R: AbstractRadixTree<K, V, Materialized = Self::Materialized>,
             F: Fn(&mut V, &V) -> bool + Copy,
         {
-            InPlaceVecMergeStateRef::merge(
-                self.children_mut(),
-                &rhs,
-                OuterCombineOp(f, PhantomData),
-                RadixTreeConverter(PhantomData),
-            );
+            self.edit_children(|children| {
+                InPlaceVecMergeStateRef::merge(
+                    children,
+                    &rhs,
+                    OuterCombineOp(f, PhantomData),
+                    RadixTreeConverter(PhantomData),
+                )
+            });
         }
 
         fn inner_combine_children_with<W, R, F>(&mut self, rhs: &[R], f: F)
@@ -184,12 +326,14 @@ pub(crate) mod internals {
             R: AbstractRadixTree<K, W>,
             F: Fn(&mut V, &W) -> bool + Copy,
         {
-            InPlaceVecMergeStateRef::merge(
-                self.children_mut(),
-                &rhs,
-                InnerCombineOp(f, PhantomData),
-                NoConverter,
-            );
+            self.edit_children(|children| {
+                InPlaceVecMergeStateRef::merge(
+                    children,
+                    &rhs,
+                    InnerCombineOp(f, PhantomData),
+                    NoConverter,
+                )
+            });
         }
 
         fn left_combine_children_with<W, R, F>(&mut self, rhs: &[R], f: F)
@@ -198,12 +342,14 @@ pub(crate) mod internals {
             R: AbstractRadixTree<K, W>,
             F: Fn(&mut V, &W) -> bool + Copy,
         {
-            InPlaceVecMergeStateRef::merge(
-                self.children_mut(),
-                &rhs,
-                LeftCombineOp(f, PhantomData),
-                NoConverter,
-            );
+            self.edit_children(|children| {
+                InPlaceVecMergeStateRef::merge(
+                    children,
+                    &rhs,
+                    LeftCombineOp(f, PhantomData),
+                    NoConverter,
+                )
+            });
         }
 
         fn retain_prefix_children_with<W, R>(&mut self, rhs: &[R], f: impl Fn(&W) -> bool + Copy)
@@ -211,12 +357,14 @@ pub(crate) mod internals {
             W: TValue,
             R: AbstractRadixTree<K, W>,
         {
-            InPlaceVecMergeStateRef::merge(
-                self.children_mut(),
-                &rhs,
-                RetainPrefixOp(f, PhantomData),
-                NoConverter,
-            );
+            self.edit_children(|children| {
+                InPlaceVecMergeStateRef::merge(
+                    children,
+                    &rhs,
+                    RetainPrefixOp(f, PhantomData),
+                    NoConverter,
+                )
+            });
         }
 
         fn remove_prefix_children_with<W, R, F>(&mut self, rhs: &[R], f: F)
@@ -225,12 +373,14 @@ pub(crate) mod internals {
             R: AbstractRadixTree<K, W>,
             F: Fn(&W) -> bool + Copy,
         {
-            InPlaceVecMergeStateRef::merge(
-                self.children_mut(),
-                &rhs,
-                RemovePrefixOp(f, PhantomData),
-                NoConverter,
-            );
+            self.edit_children(|children| {
+                InPlaceVecMergeStateRef::merge(
+                    children,
+                    &rhs,
+                    RemovePrefixOp(f, PhantomData),
+                    NoConverter,
+                )
+            });
         }
     }
 }
@@ -256,6 +406,29 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
         Self::new(key.into(), Some(value), Vec::new())
     }
 
+    /// Create a tree from key value pairs that are sorted by key, in a single pass.
+    ///
+    /// `keep` determines whether to keep the first or the last value in case of duplicate keys.
+    ///
+    /// Panics if the keys are not sorted.
+    fn from_sorted_iter<Q: AsRef<[K]>>(iter: impl IntoIterator<Item = (Q, V)>, keep: Keep) -> Self {
+        from_sorted0(iter, keep)
+    }
+
+    /// Create a tree from key value pairs in arbitrary order.
+    ///
+    /// The pairs are sorted and deduplicated first, so this will be faster for already partially sorted input.
+    /// `keep` determines whether to keep the first or the last value in case of duplicate keys.
+    fn from_unsorted_iter<Q: AsRef<[K]>>(
+        iter: impl IntoIterator<Item = (Q, V)>,
+        keep: Keep,
+    ) -> Self {
+        let sorted: Vec<(Q, V)> = sort_dedup_by(iter.into_iter(), keep, |(a, _), (b, _)| {
+            a.as_ref().cmp(b.as_ref())
+        });
+        from_sorted0(sorted, keep)
+    }
+
     /// Insert a mapping. Will replace existing mapping.
     fn insert(&mut self, key: &[K], value: V) {
         self.outer_combine_with(&Self::single(key, value), |a, b| {
@@ -264,18 +437,78 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
         })
     }
 
+    /// Get an optional mutable reference to the value for the given key
+    ///
+    /// For copy on write flavours, only the path to the key is copied. If the key is not present,
+    /// the tree is not touched.
+    fn get_mut(&mut self, key: &[K]) -> Option<&mut V> {
+        if !self.contains_key(key) {
+            return None;
+        }
+        get_mut0(self, key)
+    }
+
+    /// iterate over all elements, with mutable access to the values
+    ///
+    /// For copy on write flavours, this will copy all nodes that are shared.
+    fn iter_mut(&mut self) -> IterMut<'_, K, V, Self> {
+        IterMut::new(self)
+    }
+
+    /// iterate over all values, with mutable access
+    ///
+    /// For copy on write flavours, this will copy all nodes that are shared.
+    fn values_mut(&mut self) -> ValuesMut<'_, K, V, Self> {
+        ValuesMut::new(self)
+    }
+
+    /// Retain only the mappings for which `f` returns true.
+    ///
+    /// For copy on write flavours, only the paths to removed mappings are copied.
+    fn retain(&mut self, mut f: impl FnMut(&[K], &V) -> bool) {
+        let remove: RadixTree<K, ()> = RadixTree::from_sorted_iter(
+            self.iter().filter(|(k, v)| !f(k, v)).map(|(k, _)| (k, ())),
+            Keep::First,
+        );
+        if !remove.is_empty() {
+            self.difference_with(&remove);
+        }
+    }
+
+    /// Remove the mapping for a key. Returns the removed value, if any.
+    ///
+    /// Nodes that become degenerate are merged again, so the tree stays canonical.
+    /// If the key is not present, the tree is not touched, so no copy on write happens.
+    fn remove(&mut self, key: &[K]) -> Option<V> {
+        if !self.contains_key(key) {
+            return None;
+        }
+        remove0(self, key)
+    }
+
+    /// Remove all mappings for keys that start with the given prefix.
+    ///
+    /// Nodes that become degenerate are merged again, so the tree stays canonical.
+    /// If there are no such keys, the tree is not touched, so no copy on write happens.
+    fn remove_prefix(&mut self, prefix: &[K]) {
+        if let FindResult::NotFound { .. } = find(self, prefix) {
+            return;
+        }
+        remove_prefix0(self, prefix);
+    }
+
     /// Return the subtree with the given prefix. Will return an empty tree in case there is no match.
     fn filter_prefix(&self, prefix: &[K]) -> Self {
         match find(self, prefix) {
             FindResult::Found(tree) => {
                 let mut res = tree.clone();
-                *res.prefix_mut() = prefix.into();
+                res.set_prefix(prefix.into());
                 res
             }
             FindResult::Prefix { tree, rt } => {
+                let p = tree.prefix();
                 let mut res = tree.clone();
-                let p = res.prefix();
-                *res.prefix_mut() = Fragment::from(&p[p.len() - rt..]);
+                res.set_prefix(p[p.len() - rt..].into());
                 res.prepend(prefix);
                 res
             }
@@ -289,7 +522,7 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
             let mut prefix1 = SmallVec::new();
             prefix1.extend_from_slice(prefix);
             prefix1.extend_from_slice(self.prefix());
-            *self.prefix_mut() = prefix1.into();
+            self.set_prefix(prefix1.into());
         }
     }
 
@@ -336,6 +569,47 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
         self.left_combine_with(that, |_, _| false)
     }
 
+    /// Parallel version of [union](AbstractRadixTreeMut::union)
+    #[cfg(feature = "rayon")]
+    fn par_union(
+        &self,
+        that: &(impl AbstractRadixTree<K, V, Materialized = Self::Materialized> + Sync),
+    ) -> Self::Materialized
+    where
+        Self: Send + Sync,
+    {
+        self.par_outer_combine(that, |a, _| Some(a.clone()))
+    }
+
+    /// Parallel version of [intersection](AbstractRadixTreeMut::intersection)
+    #[cfg(feature = "rayon")]
+    fn par_intersection<W: TValue, B: AbstractRadixTree<K, W> + Sync>(
+        &self,
+        that: &B,
+    ) -> Self::Materialized
+    where
+        Self: Send + Sync,
+        B::Materialized: Sync,
+    {
+        self.par_inner_combine(that, |a, _| Some(a.clone()))
+    }
+
+    /// Parallel version of [difference](AbstractRadixTreeMut::difference)
+    #[cfg(feature = "rayon")]
+    fn par_difference<W: TValue, B: AbstractRadixTree<K, W> + Sync>(
+        &self,
+        that: &B,
+    ) -> Self::Materialized
+    where
+        Self: Send + Sync,
+        B::Materialized: Sync,
+    {
+        self.par_left_combine(
+            that,
+            |a, b| if b.is_none() { Some(a.clone()) } else { None },
+        )
+    }
+
     /// outer combine of `self` tree with `that` tree
     ///
     /// outer means that elements that are in `self` but not in `that` or vice versa are copied.
@@ -383,8 +657,10 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
         } else {
             // disjoint
             self.split(n);
-            self.children_mut().push(that.materialize_shortened(n));
-            self.children_mut().sort_by_key(|x| x.prefix()[0]);
+            self.edit_children(|children| {
+                children.push(that.materialize_shortened(n));
+                children.sort_by_key(|x| x.prefix()[0]);
+            });
         }
         self.unsplit();
     }
@@ -424,7 +700,7 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
         } else {
             // disjoint
             *self.value_mut() = None;
-            self.children_mut().clear();
+            self.edit_children(Vec::clear);
         }
         self.unsplit();
     }
@@ -481,7 +757,7 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
             match that.value() {
                 Some(value) if f(value) => {
                     *self.value_mut() = None;
-                    self.children_mut().clear();
+                    self.edit_children(Vec::clear);
                 }
                 _ => {
                     self.remove_prefix_children_with(that.children(), f);
@@ -492,7 +768,7 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
             match that.value() {
                 Some(value) if f(value) => {
                     *self.value_mut() = None;
-                    self.children_mut().clear();
+                    self.edit_children(Vec::clear);
                 }
                 _ => {
                     self.split(n);
@@ -539,7 +815,7 @@ pub trait AbstractRadixTreeMut<K: TKey, V: TValue>: internals::AbstractRadixTree
         } else {
             // disjoint, nuke it
             *self.value_mut() = None;
-            self.children_mut().clear();
+            self.edit_children(Vec::clear);
         }
         self.unsplit();
     }
@@ -565,6 +841,16 @@ pub trait AbstractRadixTree<K: TKey, V: TValue>: Sized {
     /// The children
     fn children(&self) -> &[Self];
 
+    /// The position of the child whose prefix starts with `first`, if any
+    ///
+    /// By default this is a binary search over the children. Flavours that keep an index of their children
+    /// can do better.
+    fn child_index(&self, first: &K) -> Option<usize> {
+        self.children()
+            .binary_search_by(|child| child.prefix()[0].cmp(first))
+            .ok()
+    }
+
     /// Type of a materialized, mutable version of this tree
     type Materialized: AbstractRadixTreeMut<K, V, Materialized = Self::Materialized>;
 
@@ -624,6 +910,26 @@ pub trait AbstractRadixTree<K: TKey, V: TValue>: Sized {
         }
     }
 
+    /// Create a tree with the same keys, and values mapped using `f`.
+    ///
+    /// The result can be any flavour of radix tree.
+    fn map_values<W: TValue, R: AbstractRadixTreeMut<K, W>>(
+        &self,
+        mut f: impl FnMut(&V) -> W,
+    ) -> R {
+        filter_map_values0(self, &mut |v| Some(f(v)))
+    }
+
+    /// Create a tree with values mapped using `f`, keeping only the mappings where `f` returns `Some`.
+    ///
+    /// The result can be any flavour of radix tree.
+    fn filter_map_values<W: TValue, R: AbstractRadixTreeMut<K, W>>(
+        &self,
+        mut f: impl FnMut(&V) -> Option<W>,
+    ) -> R {
+        filter_map_values0(self, &mut f)
+    }
+
     /// true if the keys of self are a subset of the keys of that.
     ///
     /// a set is considered to be a subset of itself.
@@ -677,6 +983,113 @@ pub trait AbstractRadixTree<K: TKey, V: TValue>: Sized {
         left_combine(self, that, f)
     }
 
+    /// Parallel version of [outer_combine](AbstractRadixTree::outer_combine), with the same result
+    ///
+    /// The children of each node are combined in parallel, until there are enough tasks for all threads.
+    #[cfg(feature = "rayon")]
+    fn par_outer_combine(
+        &self,
+        that: &(impl AbstractRadixTree<K, V, Materialized = Self::Materialized> + Sync),
+        f: impl Fn(&V, &V) -> Option<V> + Copy + Send + Sync,
+    ) -> Self::Materialized
+    where
+        Self: Sync,
+        Self::Materialized: Send + Sync,
+    {
+        rayon_support::par_outer_combine(self, that, f, rayon_support::budget())
+    }
+
+    /// Parallel version of [inner_combine](AbstractRadixTree::inner_combine), with the same result
+    #[cfg(feature = "rayon")]
+    fn par_inner_combine<W: TValue, B: AbstractRadixTree<K, W> + Sync>(
+        &self,
+        that: &B,
+        f: impl Fn(&V, &W) -> Option<V> + Copy + Send + Sync,
+    ) -> Self::Materialized
+    where
+        Self: Sync,
+        Self::Materialized: Send + Sync,
+        B::Materialized: Sync,
+    {
+        rayon_support::par_inner_combine(self, that, f, rayon_support::budget())
+    }
+
+    /// Parallel version of [left_combine](AbstractRadixTree::left_combine), with the same result
+    #[cfg(feature = "rayon")]
+    fn par_left_combine<W: TValue, B: AbstractRadixTree<K, W> + Sync>(
+        &self,
+        that: &B,
+        f: impl Fn(&V, Option<&W>) -> Option<V> + Copy + Send + Sync,
+    ) -> Self::Materialized
+    where
+        Self: Sync,
+        Self::Materialized: Send + Sync,
+        B::Materialized: Sync,
+    {
+        rayon_support::par_left_combine(self, that, f, rayon_support::budget())
+    }
+
+    /// Outer join this tree with another tree, producing a tree with a new value type.
+    ///
+    /// `f` is called with the full key and the values from one or both trees. Mappings for which it
+    /// returns `None` are dropped. The result can be any flavour of radix tree.
+    fn outer_join<W: TValue, R: TValue, T: AbstractRadixTreeMut<K, R>>(
+        &self,
+        that: &impl AbstractRadixTree<K, W>,
+        f: impl Fn(OuterJoinArg<&[K], &V, &W>) -> Option<R>,
+    ) -> T {
+        join0(
+            &mut Vec::new(),
+            Some((self, 0)),
+            Some((that, 0)),
+            (true, true),
+            &f,
+        )
+    }
+
+    /// Left join this tree with another tree, producing a tree with a new value type.
+    ///
+    /// Subtrees that only exist in `that` are skipped without visiting them.
+    fn left_join<W: TValue, R: TValue, T: AbstractRadixTreeMut<K, R>>(
+        &self,
+        that: &impl AbstractRadixTree<K, W>,
+        f: impl Fn(&[K], &V, Option<&W>) -> Option<R>,
+    ) -> T {
+        let f = |arg: OuterJoinArg<&[K], &V, &W>| match arg {
+            OuterJoinArg::Left(k, v) => f(k, v, None),
+            OuterJoinArg::Both(k, v, w) => f(k, v, Some(w)),
+            OuterJoinArg::Right(..) => None,
+        };
+        join0(
+            &mut Vec::new(),
+            Some((self, 0)),
+            Some((that, 0)),
+            (true, false),
+            &f,
+        )
+    }
+
+    /// Inner join this tree with another tree, producing a tree with a new value type.
+    ///
+    /// Subtrees that only exist in one of the trees are skipped without visiting them.
+    fn inner_join<W: TValue, R: TValue, T: AbstractRadixTreeMut<K, R>>(
+        &self,
+        that: &impl AbstractRadixTree<K, W>,
+        f: impl Fn(&[K], &V, &W) -> Option<R>,
+    ) -> T {
+        let f = |arg: OuterJoinArg<&[K], &V, &W>| match arg {
+            OuterJoinArg::Both(k, v, w) => f(k, v, w),
+            _ => None,
+        };
+        join0(
+            &mut Vec::new(),
+            Some((self, 0)),
+            Some((that, 0)),
+            (false, false),
+            &f,
+        )
+    }
+
     /// An iterator for all pairs with a certain prefix
     fn scan_prefix<'a>(&'a self, prefix: &'a [K]) -> Iter<'a, K, V, Self> {
         match find(self, prefix) {
@@ -693,6 +1106,72 @@ pub trait AbstractRadixTree<K: TKey, V: TValue>: Sized {
             FindResult::NotFound { .. } => Iter::empty(),
         }
     }
+
+    /// An iterator over all mappings whose key is a prefix of the given key, from shortest to longest.
+    ///
+    /// The items are the length of the matching prefix and a reference to the value.
+    fn prefixes_of<'a, 'b>(&'a self, key: &'b [K]) -> PrefixesOf<'a, 'b, K, V, Self> {
+        PrefixesOf::new(self, key)
+    }
+
+    /// Find the mapping with the longest key that is a prefix of the given key.
+    ///
+    /// Returns the length of the matching prefix and a reference to the value.
+    fn longest_prefix_match(&self, key: &[K]) -> Option<(usize, &V)> {
+        self.prefixes_of(key).last()
+    }
+
+    /// An iterator over all mappings whose key is within Levenshtein distance `max_distance` of the given key,
+    /// in lexicographic order of the keys.
+    ///
+    /// The items are the key, a reference to the value and the distance. Subtrees that can not contain a key
+    /// within the distance are skipped without visiting them.
+    fn fuzzy_search<'a, 'b>(
+        &'a self,
+        key: &'b [K],
+        max_distance: usize,
+    ) -> FuzzySearch<'a, 'b, K, V, Self> {
+        FuzzySearch::new(self, key, max_distance)
+    }
+
+    /// An iterator over all mappings whose key matches the given pattern, in lexicographic order of the keys.
+    ///
+    /// Subtrees that can not contain a matching key are skipped without visiting them.
+    fn matches<'a, 'b>(&'a self, pattern: &'b Pattern<K>) -> Matches<'a, 'b, K, V, Self> {
+        Matches::new(self, pattern)
+    }
+
+    /// An iterator over all pairs with a key in the given range, in lexicographic order of the keys.
+    ///
+    /// Subtrees that are entirely outside the range are skipped without visiting them.
+    fn range<'a, Q: AsRef<[K]>>(&'a self, range: impl RangeBounds<Q>) -> Range<'a, K, V, Self> {
+        let (lower, upper) = owned_bounds(&range);
+        Range::new(self, lower, upper)
+    }
+
+    /// An iterator over all pairs with a key in the given range, in reverse lexicographic order of the keys.
+    fn range_rev<'a, Q: AsRef<[K]>>(
+        &'a self,
+        range: impl RangeBounds<Q>,
+    ) -> RangeRev<'a, K, V, Self> {
+        let (lower, upper) = owned_bounds(&range);
+        RangeRev::new(self, lower, upper)
+    }
+
+    /// iterate over all elements in reverse order
+    fn iter_rev(&self) -> RangeRev<'_, K, V, Self> {
+        RangeRev::new(self, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// The pair with the smallest key, if any
+    fn first(&self) -> Option<(IterKey<K>, &V)> {
+        self.iter().next()
+    }
+
+    /// The pair with the largest key, if any
+    fn last(&self) -> Option<(IterKey<K>, &V)> {
+        self.iter_rev().next()
+    }
 }
 
 enum FindResult<T> {
@@ -738,7 +1217,7 @@ fn find<'a, K: TKey, V: TValue, T: AbstractRadixTree<K, V>>(
     } else if rt == 0 {
         // prefix is a subtree of tree
         let c = &prefix[n];
-        if let Ok(index) = tree.children().binary_search_by(|e| e.prefix()[0].cmp(c)) {
+        if let Some(index) = tree.child_index(c) {
             let child = &tree.children()[index];
             find(child, &prefix[n..])
         } else {
@@ -758,10 +1237,162 @@ fn find<'a, K: TKey, V: TValue, T: AbstractRadixTree<K, V>>(
     }
 }
 
+/// remove the value for a key that is known to be present in the tree
+fn remove0<K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>>(
+    tree: &mut T,
+    key: &[K],
+) -> Option<V> {
+    let rest = &key[tree.prefix().len()..];
+    let res = if rest.is_empty() {
+        tree.value_mut().take()
+    } else {
+        let index = tree.child_index(&rest[0])?;
+        remove0(&mut tree.children_mut()[index], rest)
+    };
+    // removes the child if it became empty, and merges with the remaining child if we became degenerate
+    tree.unsplit();
+    res
+}
+
+/// remove everything starting with a prefix that is known to be present in the tree
+fn remove_prefix0<K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>>(tree: &mut T, prefix: &[K]) {
+    let n = common_prefix(tree.prefix(), prefix);
+    if n == prefix.len() {
+        // everything in this tree starts with the prefix
+        *tree = T::default();
+    } else if n == tree.prefix().len() {
+        let rest = &prefix[n..];
+        if let Some(index) = tree.child_index(&rest[0]) {
+            remove_prefix0(&mut tree.children_mut()[index], rest);
+            tree.unsplit();
+        }
+    }
+}
+
+fn get_mut0<'a, K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>>(
+    tree: &'a mut T,
+    key: &[K],
+) -> Option<&'a mut V> {
+    if !key.starts_with(tree.prefix()) {
+        return None;
+    }
+    let rest = &key[tree.prefix().len()..];
+    if rest.is_empty() {
+        return tree.value_mut().as_mut();
+    }
+    let index = tree.child_index(&rest[0])?;
+    get_mut0(&mut tree.children_mut()[index], rest)
+}
+
+fn filter_map_values0<K, V, W, T, R>(tree: &T, f: &mut impl FnMut(&V) -> Option<W>) -> R
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    T: AbstractRadixTree<K, V>,
+    R: AbstractRadixTreeMut<K, W>,
+{
+    let value = tree.value().and_then(&mut *f);
+    let children = tree
+        .children()
+        .iter()
+        .map(|child| filter_map_values0(child, f))
+        .collect();
+    let mut res = R::new(tree.prefix().into(), value, children);
+    // removing values might have made the node degenerate
+    res.unsplit();
+    res
+}
+
+/// A node under construction in [from_sorted0]. The prefix is path[start..end] of the current path.
+struct OpenNode<T, V> {
+    start: usize,
+    end: usize,
+    value: Option<V>,
+    children: Vec<T>,
+}
+
+impl<T, V> OpenNode<T, V> {
+    fn new(start: usize, end: usize, value: Option<V>) -> Self {
+        Self {
+            start,
+            end,
+            value,
+            children: Vec::new(),
+        }
+    }
+
+    fn close<K: TKey>(self, path: &[K]) -> T
+    where
+        V: TValue,
+        T: AbstractRadixTreeMut<K, V>,
+    {
+        T::new(path[self.start..self.end].into(), self.value, self.children)
+    }
+}
+
+/// build a tree from sorted key value pairs in a single pass
+///
+/// keeps a stack of the nodes along the path of the last key. Nodes that are no longer on the
+/// path of the current key are finished and added to their parent.
+fn from_sorted0<K, V, T, Q>(iter: impl IntoIterator<Item = (Q, V)>, keep: Keep) -> T
+where
+    K: TKey,
+    V: TValue,
+    T: AbstractRadixTreeMut<K, V>,
+    Q: AsRef<[K]>,
+{
+    let mut path: Vec<K> = Vec::new();
+    let mut stack: Vec<OpenNode<T, V>> = vec![OpenNode::new(0, 0, None)];
+    let mut first = true;
+    for (key, value) in iter {
+        let key = key.as_ref();
+        let n = common_prefix(&path, key);
+        if !first && n == key.len() && n == path.len() {
+            // duplicate key. The deepest node on the stack is the node for the last key
+            if keep == Keep::Last {
+                stack.last_mut().unwrap().value = Some(value);
+            }
+            continue;
+        }
+        assert!(
+            first || (n < key.len() && (n == path.len() || key[n] > path[n])),
+            "keys must be sorted"
+        );
+        first = false;
+        // finish all nodes that are not on the path of the new key
+        while stack.len() > 1 && stack.last().unwrap().start >= n {
+            let node = stack.pop().unwrap().close(&path);
+            stack.last_mut().unwrap().children.push(node);
+        }
+        let top = stack.last_mut().unwrap();
+        if top.end > n {
+            // the new key branches off within the prefix of the top node, so split it
+            let mut child = OpenNode::new(n, top.end, top.value.take());
+            child.children = std::mem::take(&mut top.children);
+            top.children.push(child.close(&path));
+            top.end = n;
+        }
+        path.truncate(n);
+        path.extend_from_slice(&key[n..]);
+        if key.len() == n {
+            // this can only happen for the empty key as the first key
+            top.value = Some(value);
+        } else {
+            stack.push(OpenNode::new(n, key.len(), Some(value)));
+        }
+    }
+    while stack.len() > 1 {
+        let node = stack.pop().unwrap().close(&path);
+        stack.last_mut().unwrap().children.push(node);
+    }
+    let mut res = stack.pop().unwrap().close(&path);
+    res.unsplit();
+    res
+}
+
 fn materialize<T, K: TKey, V: TValue>(tree: &T) -> T::Materialized
 where
-    K: Clone,
-    V: Clone,
     T: AbstractRadixTree<K, V>,
 {
     materialize_shortened(tree, 0)
@@ -769,8 +1400,6 @@ where
 
 fn materialize_shortened<T, K: TKey, V: TValue>(tree: &T, n: usize) -> T::Materialized
 where
-    K: Clone,
-    V: Clone,
     T: AbstractRadixTree<K, V>,
 {
     assert!(n < tree.prefix().len());
@@ -784,7 +1413,7 @@ where
 /// Key for iteration
 ///
 /// This refers to a temporary key that is being constructed during iteration. Cloning it will make a copy.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct IterKey<K>(Arc<Vec<K>>);
 
 impl<K: Clone> IterKey<K> {
@@ -826,51 +1455,277 @@ impl<T> core::ops::Deref for IterKey<T> {
     }
 }
 
-/// An iterator over the values of a radix tree.
+/// An iterator over all mappings of a radix tree whose key is a prefix of a given key.
 ///
-/// This is more efficient than taking the value part of an entry iteration, because the keys
-/// do not have to be constructed.
-pub struct Values<'a, K, V, T> {
-    stack: Vec<(&'a T, usize)>,
-    _p: PhantomData<(K, V)>,
+/// This is created by [prefixes_of](AbstractRadixTree::prefixes_of). It walks down a single path
+/// of the tree, so it never visits more nodes than the depth of the tree.
+pub struct PrefixesOf<'a, 'b, K, V, T> {
+    tree: Option<&'a T>,
+    key: &'b [K],
+    offset: usize,
+    _v: PhantomData<V>,
 }
 
-impl<'a, K, V, T> Values<'a, K, V, T> {
-    fn new(tree: &'a T) -> Self {
+impl<'a, 'b, K, V, T> PrefixesOf<'a, 'b, K, V, T> {
+    fn new(tree: &'a T, key: &'b [K]) -> Self {
         Self {
-            stack: vec![(tree, 0)],
-            _p: PhantomData,
+            tree: Some(tree),
+            key,
+            offset: 0,
+            _v: PhantomData,
         }
     }
-
-    fn tree(&self) -> &'a T {
-        self.stack.last().unwrap().0
-    }
-
-    fn inc(&mut self) -> Option<usize> {
-        let pos = &mut self.stack.last_mut().unwrap().1;
-        let res = if *pos == 0 { None } else { Some(*pos - 1) };
-        *pos += 1;
-        res
-    }
 }
 
-impl<'a, K: TKey, V: TValue, T> Iterator for Values<'a, K, V, T>
-where
-    T: AbstractRadixTree<K, V>,
+impl<'a, 'b, K: TKey, V: 'a + TValue, T: AbstractRadixTree<K, V>> Iterator
+    for PrefixesOf<'a, 'b, K, V, T>
 {
-    type Item = &'a V;
+    type Item = (usize, &'a V);
 
     fn next(&mut self) -> Option<Self::Item> {
-        while !self.stack.is_empty() {
-            if let Some(pos) = self.inc() {
-                if pos < self.tree().children().len() {
-                    self.stack.push((&self.tree().children()[pos], 0));
-                } else {
-                    self.stack.pop();
-                }
-            } else if let Some(value) = self.tree().value() {
-                return Some(value);
+        while let Some(tree) = self.tree.take() {
+            let rest = &self.key[self.offset..];
+            if !rest.starts_with(tree.prefix()) {
+                break;
+            }
+            self.offset += tree.prefix().len();
+            if let Some(c) = self.key.get(self.offset) {
+                self.tree = tree.child_index(c).map(|index| &tree.children()[index]);
+            }
+            if let Some(value) = tree.value() {
+                return Some((self.offset, value));
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over all mappings of a radix tree whose key is within a maximum edit distance of a given key.
+///
+/// This is created by [fuzzy_search](AbstractRadixTree::fuzzy_search). It keeps one row of the Levenshtein
+/// matrix for each element of the current path, so going down the tree only has to compute the rows for the
+/// prefix of the child.
+pub struct FuzzySearch<'a, 'b, K, V, T> {
+    key: &'b [K],
+    max_distance: usize,
+    path: IterKey<K>,
+    /// rows of the matrix, each of length key.len() + 1, for the empty path and each element of the path
+    rows: Vec<usize>,
+    stack: Vec<(&'a T, usize)>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, 'b, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> FuzzySearch<'a, 'b, K, V, T> {
+    fn new(tree: &'a T, key: &'b [K], max_distance: usize) -> Self {
+        let mut res = Self {
+            key,
+            max_distance,
+            path: IterKey::new(&[]),
+            rows: (0..=key.len()).collect(),
+            stack: Vec::new(),
+            _v: PhantomData,
+        };
+        res.descend(tree);
+        res
+    }
+
+    fn width(&self) -> usize {
+        self.key.len() + 1
+    }
+
+    /// the distance of the current path to the key
+    fn distance(&self) -> usize {
+        self.rows[self.rows.len() - 1]
+    }
+
+    /// push a node unless no key in it can be within the maximum distance
+    fn descend(&mut self, tree: &'a T) {
+        let w = self.width();
+        let start = self.rows.len();
+        for k in tree.prefix() {
+            let prev = self.rows.len() - w;
+            self.rows.push(self.rows[prev] + 1);
+            for j in 1..w {
+                let substitute = self.rows[prev + j - 1] + usize::from(self.key[j - 1] != *k);
+                let delete = self.rows[prev + j] + 1;
+                let insert = self.rows[self.rows.len() - 1] + 1;
+                self.rows.push(substitute.min(delete).min(insert));
+            }
+            // the distance can only grow from the minimum of the row
+            if self.rows[self.rows.len() - w..].iter().min() > Some(&self.max_distance) {
+                self.rows.truncate(start);
+                return;
+            }
+        }
+        self.path.append(tree.prefix());
+        self.stack.push((tree, 0));
+    }
+
+    fn ascend(&mut self) {
+        let (tree, _) = self.stack.pop().unwrap();
+        let n = tree.prefix().len();
+        self.path.pop(n);
+        self.rows.truncate(self.rows.len() - n * self.width());
+    }
+}
+
+impl<'a, 'b, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> SortedByKey
+    for FuzzySearch<'a, 'b, K, V, T>
+{
+}
+
+impl<'a, 'b, K: TKey, V: 'a + TValue, T: AbstractRadixTree<K, V>> Iterator
+    for FuzzySearch<'a, 'b, K, V, T>
+{
+    type Item = (IterKey<K>, &'a V, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((tree, pos)) = self.stack.last_mut() {
+            let tree: &'a T = tree;
+            let index = *pos;
+            *pos += 1;
+            if index == 0 {
+                let distance = self.distance();
+                if let Some(value) = tree.value() {
+                    if distance <= self.max_distance {
+                        return Some((self.path.clone(), value, distance));
+                    }
+                }
+            } else if let Some(child) = tree.children().get(index - 1) {
+                self.descend(child);
+            } else {
+                self.ascend();
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over all mappings of a radix tree whose key matches a [Pattern].
+///
+/// This is created by [matches](AbstractRadixTree::matches). Like [FuzzySearch], it keeps the states of the
+/// pattern automaton for each element of the current path.
+pub struct Matches<'a, 'b, K, V, T> {
+    pattern: &'b Pattern<K>,
+    path: IterKey<K>,
+    /// pattern states for the empty path and each element of the path
+    states: Vec<bool>,
+    stack: Vec<(&'a T, usize)>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, 'b, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> Matches<'a, 'b, K, V, T> {
+    fn new(tree: &'a T, pattern: &'b Pattern<K>) -> Self {
+        let mut res = Self {
+            pattern,
+            path: IterKey::new(&[]),
+            states: Vec::new(),
+            stack: Vec::new(),
+            _v: PhantomData,
+        };
+        pattern.start(&mut res.states);
+        res.descend(tree);
+        res
+    }
+
+    /// push a node unless no key in it can match
+    fn descend(&mut self, tree: &'a T) {
+        let start = self.states.len();
+        for k in tree.prefix() {
+            if !self.pattern.step(&mut self.states, k) {
+                self.states.truncate(start);
+                return;
+            }
+        }
+        self.path.append(tree.prefix());
+        self.stack.push((tree, 0));
+    }
+
+    fn ascend(&mut self) {
+        let (tree, _) = self.stack.pop().unwrap();
+        let n = tree.prefix().len();
+        self.path.pop(n);
+        self.states
+            .truncate(self.states.len() - n * self.pattern.width());
+    }
+}
+
+impl<'a, 'b, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> SortedByKey
+    for Matches<'a, 'b, K, V, T>
+{
+}
+
+impl<'a, 'b, K: TKey, V: 'a + TValue, T: AbstractRadixTree<K, V>> Iterator
+    for Matches<'a, 'b, K, V, T>
+{
+    type Item = (IterKey<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((tree, pos)) = self.stack.last_mut() {
+            let tree: &'a T = tree;
+            let index = *pos;
+            *pos += 1;
+            if index == 0 {
+                if let Some(value) = tree.value() {
+                    if self.pattern.is_accepting(&self.states) {
+                        return Some((self.path.clone(), value));
+                    }
+                }
+            } else if let Some(child) = tree.children().get(index - 1) {
+                self.descend(child);
+            } else {
+                self.ascend();
+            }
+        }
+        None
+    }
+}
+
+/// An iterator over the values of a radix tree.
+///
+/// This is more efficient than taking the value part of an entry iteration, because the keys
+/// do not have to be constructed.
+pub struct Values<'a, K, V, T> {
+    stack: Vec<(&'a T, usize)>,
+    _p: PhantomData<(K, V)>,
+}
+
+impl<'a, K, V, T> Values<'a, K, V, T> {
+    fn new(tree: &'a T) -> Self {
+        Self {
+            stack: vec![(tree, 0)],
+            _p: PhantomData,
+        }
+    }
+
+    fn tree(&self) -> &'a T {
+        self.stack.last().unwrap().0
+    }
+
+    fn inc(&mut self) -> Option<usize> {
+        let pos = &mut self.stack.last_mut().unwrap().1;
+        let res = if *pos == 0 { None } else { Some(*pos - 1) };
+        *pos += 1;
+        res
+    }
+}
+
+impl<'a, K: TKey, V: TValue, T> Iterator for Values<'a, K, V, T>
+where
+    T: AbstractRadixTree<K, V>,
+{
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.stack.is_empty() {
+            if let Some(pos) = self.inc() {
+                if pos < self.tree().children().len() {
+                    self.stack.push((&self.tree().children()[pos], 0));
+                } else {
+                    self.stack.pop();
+                }
+            } else if let Some(value) = self.tree().value() {
+                return Some(value);
             }
         }
         None
@@ -940,111 +1795,430 @@ impl<'a, K: TKey, V: 'a + TValue, T: AbstractRadixTree<K, V>> Iterator for Iter<
     }
 }
 
-struct RadixTreeConverter<K, V>(PhantomData<(K, V)>);
+/// An iterator over the elements (key and mutable value) of a radix tree
+///
+/// This is created by [iter_mut](AbstractRadixTreeMut::iter_mut).
+pub struct IterMut<'a, K, V, T> {
+    path: IterKey<K>,
+    /// prefix length, value (if not yet returned) and remaining children of each node on the path
+    stack: Vec<(usize, Option<&'a mut V>, std::slice::IterMut<'a, T>)>,
+}
 
-impl<T: AbstractRadixTree<K, V>, K: TKey, V: TValue> Converter<&T, T::Materialized>
-    for RadixTreeConverter<K, V>
-{
-    fn convert(value: &T) -> T::Materialized {
-        materialize(value)
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>> IterMut<'a, K, V, T> {
+    fn new(tree: &'a mut T) -> Self {
+        let path = IterKey::new(tree.prefix());
+        let mut res = Self {
+            path,
+            stack: Vec::new(),
+        };
+        res.push(0, tree);
+        res
+    }
+
+    fn push(&mut self, prefix_len: usize, tree: &'a mut T) {
+        let (value, children) = tree.value_and_children_mut();
+        self.stack
+            .push((prefix_len, value.as_mut(), children.iter_mut()));
     }
 }
 
-fn is_subset<K: TKey, V: TValue, W: TValue>(
-    l: &impl AbstractRadixTree<K, V>,
-    r: &impl AbstractRadixTree<K, W>,
-) -> bool {
-    is_subset0(l, l.prefix(), r, r.prefix())
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>> SortedByKey for IterMut<'a, K, V, T> {}
+
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>> Iterator for IterMut<'a, K, V, T> {
+    type Item = (IterKey<K>, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((prefix_len, value, children)) = self.stack.last_mut() {
+            if let Some(value) = value.take() {
+                return Some((self.path.clone(), value));
+            } else if let Some(child) = children.next() {
+                self.path.append(child.prefix());
+                self.push(child.prefix().len(), child);
+            } else {
+                self.path.pop(*prefix_len);
+                self.stack.pop();
+            }
+        }
+        None
+    }
 }
 
-fn is_subset0<K: TKey, V: TValue, W: TValue>(
-    l: &impl AbstractRadixTree<K, V>,
-    l_prefix: &[K],
-    r: &impl AbstractRadixTree<K, W>,
-    r_prefix: &[K],
-) -> bool {
-    let n = common_prefix(l_prefix, r_prefix);
-    if n == l_prefix.len() && n == r_prefix.len() {
-        // prefixes are identical
-        (l.value().is_none() || r.value().is_some())
-            && !BoolOpMergeState::merge(l.children(), r.children(), NonSubsetOp(PhantomData))
-    } else if n == l_prefix.len() {
-        // l is a prefix of r - shorten r_prefix
-        let r_prefix = &r_prefix[n..];
-        // if l has a value but not r, we found one
-        // if one or more of lc are not a subset of r, we are done
-        l.value().is_none()
-            && l.children()
-                .iter()
-                .all(|lc| is_subset0(lc, lc.prefix(), r, r_prefix))
-    } else if n == r_prefix.len() {
-        // r is a prefix of l - shorten L_prefix
-        let l_prefix = &l_prefix[n..];
-        // if l is a subset of none of rc, we are done
-        r.children()
-            .iter()
-            .any(|rc| is_subset0(l, l_prefix, rc, rc.prefix()))
-    } else {
-        // disjoint
-        false
+/// An iterator over the mutable values of a radix tree
+///
+/// This is created by [values_mut](AbstractRadixTreeMut::values_mut).
+pub struct ValuesMut<'a, K, V, T> {
+    stack: Vec<(Option<&'a mut V>, std::slice::IterMut<'a, T>)>,
+    _k: PhantomData<K>,
+}
+
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>> ValuesMut<'a, K, V, T> {
+    fn new(tree: &'a mut T) -> Self {
+        let mut res = Self {
+            stack: Vec::new(),
+            _k: PhantomData,
+        };
+        res.push(tree);
+        res
+    }
+
+    fn push(&mut self, tree: &'a mut T) {
+        let (value, children) = tree.value_and_children_mut();
+        self.stack.push((value.as_mut(), children.iter_mut()));
     }
 }
 
-fn intersects<K: TKey, V: TValue, W: TValue>(
-    l: &impl AbstractRadixTree<K, V>,
-    r: &impl AbstractRadixTree<K, W>,
-) -> bool {
-    intersects0(l, l.prefix(), r, r.prefix())
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTreeMut<K, V>> Iterator for ValuesMut<'a, K, V, T> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((value, children)) = self.stack.last_mut() {
+            if let Some(value) = value.take() {
+                return Some(value);
+            } else if let Some(child) = children.next() {
+                self.push(child);
+            } else {
+                self.stack.pop();
+            }
+        }
+        None
+    }
 }
 
-fn intersects0<K: TKey, V: TValue, W: TValue>(
-    l: &impl AbstractRadixTree<K, V>,
-    l_prefix: &[K],
-    r: &impl AbstractRadixTree<K, W>,
-    r_prefix: &[K],
-) -> bool {
-    let n = common_prefix(l_prefix, r_prefix);
-    if n == l_prefix.len() && n == r_prefix.len() {
-        // prefixes are identical
-        (l.value().is_some() && r.value().is_some())
-            || BoolOpMergeState::merge(l.children(), r.children(), IntersectOp(PhantomData))
-    } else if n == l_prefix.len() {
-        // l is a prefix of r
-        let r_prefix = &r_prefix[n..];
-        l.children()
-            .iter()
-            .any(|lc| intersects0(lc, lc.prefix(), r, r_prefix))
-    } else if n == r_prefix.len() {
-        // r is a prefix of l
-        let l_prefix = &l_prefix[n..];
-        r.children()
-            .iter()
-            .any(|rc| intersects0(l, l_prefix, rc, rc.prefix()))
+fn owned_bounds<K: Clone, Q: AsRef<[K]>>(
+    range: &impl RangeBounds<Q>,
+) -> (Bound<Vec<K>>, Bound<Vec<K>>) {
+    fn owned<K: Clone, Q: AsRef<[K]>>(bound: Bound<&Q>) -> Bound<Vec<K>> {
+        match bound {
+            Bound::Included(x) => Bound::Included(x.as_ref().to_vec()),
+            Bound::Excluded(x) => Bound::Excluded(x.as_ref().to_vec()),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+    (owned(range.start_bound()), owned(range.end_bound()))
+}
+
+/// How all keys of a subtree with the given path compare to a bound
+///
+/// Returns None if the path is a prefix of the bound, in which case the subtree can contain
+/// keys on both sides of the bound.
+fn subtree_cmp<K: Ord>(path: &[K], bound: &[K]) -> Option<Ordering> {
+    let n = common_prefix(path, bound);
+    if n == path.len() {
+        None
+    } else if n == bound.len() {
+        Some(Ordering::Greater)
     } else {
-        // disjoint
-        false
+        Some(path[n].cmp(&bound[n]))
     }
 }
 
-/// Outer combine two trees with a function f
-fn outer_combine<
-    K: TKey,
-    V: TValue,
-    R: AbstractRadixTreeMut<K, V, Materialized = R>,
-    A: AbstractRadixTree<K, V, Materialized = R>,
-    B: AbstractRadixTree<K, V, Materialized = R>,
->(
-    a: &A,
-    b: &B,
-    f: impl Fn(&V, &V) -> Option<V> + Copy,
-) -> R {
-    let n = common_prefix(a.prefix(), b.prefix());
-    let prefix = a.prefix()[..n].into();
-    let mut children = Vec::new();
-    let mut value = None;
-    if n == a.prefix().len() && n == b.prefix().len() {
-        // prefixes are identical
-        value = match (a.value(), b.value()) {
+/// true if the key is below the lower bound
+fn below<K: Ord>(key: &[K], lower: &Bound<Vec<K>>) -> bool {
+    match lower {
+        Bound::Included(l) => key < l.as_slice(),
+        Bound::Excluded(l) => key <= l.as_slice(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// true if the key is above the upper bound
+fn above<K: Ord>(key: &[K], upper: &Bound<Vec<K>>) -> bool {
+    match upper {
+        Bound::Included(u) => key > u.as_slice(),
+        Bound::Excluded(u) => key >= u.as_slice(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// true if all keys of a subtree with the given path are below the lower bound
+fn subtree_below<K: Ord>(path: &[K], lower: &Bound<Vec<K>>) -> bool {
+    match lower {
+        Bound::Included(l) | Bound::Excluded(l) => subtree_cmp(path, l) == Some(Ordering::Less),
+        Bound::Unbounded => false,
+    }
+}
+
+/// true if all keys of a subtree with the given path are above the upper bound
+fn subtree_above<K: Ord>(path: &[K], upper: &Bound<Vec<K>>) -> bool {
+    match upper {
+        Bound::Included(u) => subtree_cmp(path, u) == Some(Ordering::Greater),
+        // the node itself is excluded, and all its children are greater
+        Bound::Excluded(u) => {
+            path == u.as_slice() || subtree_cmp(path, u) == Some(Ordering::Greater)
+        }
+        Bound::Unbounded => false,
+    }
+}
+
+/// An iterator over the elements of a radix tree with keys in a range
+///
+/// This is created by [range](AbstractRadixTree::range).
+pub struct Range<'a, K, V, T> {
+    path: IterKey<K>,
+    // node, index of the next child to visit, and whether the value still has to be visited
+    stack: Vec<(&'a T, usize, bool)>,
+    lower: Bound<Vec<K>>,
+    upper: Bound<Vec<K>>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> Range<'a, K, V, T> {
+    fn new(tree: &'a T, lower: Bound<Vec<K>>, upper: Bound<Vec<K>>) -> Self {
+        let mut res = Self {
+            path: IterKey::new(tree.prefix()),
+            stack: Vec::new(),
+            lower,
+            upper,
+            _v: PhantomData,
+        };
+        if !subtree_below(&res.path, &res.lower) && !subtree_above(&res.path, &res.upper) {
+            res.push(tree);
+        }
+        res
+    }
+
+    fn push(&mut self, tree: &'a T) {
+        let path: &[K] = &self.path;
+        // skip all children that are entirely below the lower bound
+        let start = match &self.lower {
+            Bound::Included(l) | Bound::Excluded(l)
+                if path.len() < l.len() && l.starts_with(path) =>
+            {
+                let c = &l[path.len()];
+                tree.children().partition_point(|e| e.prefix()[0] < *c)
+            }
+            _ => 0,
+        };
+        self.stack.push((tree, start, tree.value().is_some()));
+    }
+}
+
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> SortedByKey for Range<'a, K, V, T> {}
+
+impl<'a, K: TKey, V: 'a + TValue, T: AbstractRadixTree<K, V>> Iterator for Range<'a, K, V, T> {
+    type Item = (IterKey<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((tree, pos, value)) = self.stack.last_mut() {
+            let tree: &'a T = tree;
+            if *value {
+                *value = false;
+                if above(&self.path, &self.upper) {
+                    break;
+                }
+                if !below(&self.path, &self.lower) {
+                    return Some((self.path.clone(), tree.value().unwrap()));
+                }
+            } else if *pos < tree.children().len() {
+                let child = &tree.children()[*pos];
+                *pos += 1;
+                self.path.append(child.prefix());
+                if subtree_above(&self.path, &self.upper) {
+                    // all subsequent subtrees are above as well
+                    break;
+                }
+                if subtree_below(&self.path, &self.lower) {
+                    self.path.pop(child.prefix().len());
+                } else {
+                    self.push(child);
+                }
+            } else {
+                self.path.pop(tree.prefix().len());
+                self.stack.pop();
+            }
+        }
+        self.stack.clear();
+        None
+    }
+}
+
+/// An iterator over the elements of a radix tree with keys in a range, in reverse order
+///
+/// This is created by [range_rev](AbstractRadixTree::range_rev) and [iter_rev](AbstractRadixTree::iter_rev).
+pub struct RangeRev<'a, K, V, T> {
+    path: IterKey<K>,
+    // node and number of children that remain to be visited
+    stack: Vec<(&'a T, usize)>,
+    lower: Bound<Vec<K>>,
+    upper: Bound<Vec<K>>,
+    _v: PhantomData<V>,
+}
+
+impl<'a, K: TKey, V: TValue, T: AbstractRadixTree<K, V>> RangeRev<'a, K, V, T> {
+    fn new(tree: &'a T, lower: Bound<Vec<K>>, upper: Bound<Vec<K>>) -> Self {
+        let mut res = Self {
+            path: IterKey::new(tree.prefix()),
+            stack: Vec::new(),
+            lower,
+            upper,
+            _v: PhantomData,
+        };
+        if !subtree_below(&res.path, &res.lower) && !subtree_above(&res.path, &res.upper) {
+            res.push(tree);
+        }
+        res
+    }
+
+    fn push(&mut self, tree: &'a T) {
+        let path: &[K] = &self.path;
+        // skip all children that are entirely above the upper bound
+        let end = match &self.upper {
+            Bound::Included(u) | Bound::Excluded(u)
+                if path.len() < u.len() && u.starts_with(path) =>
+            {
+                let c = &u[path.len()];
+                tree.children().partition_point(|e| e.prefix()[0] <= *c)
+            }
+            _ => tree.children().len(),
+        };
+        self.stack.push((tree, end));
+    }
+}
+
+impl<'a, K: TKey, V: 'a + TValue, T: AbstractRadixTree<K, V>> Iterator for RangeRev<'a, K, V, T> {
+    type Item = (IterKey<K>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((tree, remaining)) = self.stack.last_mut() {
+            let tree: &'a T = tree;
+            if *remaining > 0 {
+                *remaining -= 1;
+                let child = &tree.children()[*remaining];
+                self.path.append(child.prefix());
+                if subtree_below(&self.path, &self.lower) {
+                    // all subsequent subtrees are below as well
+                    break;
+                }
+                if subtree_above(&self.path, &self.upper) {
+                    self.path.pop(child.prefix().len());
+                } else {
+                    self.push(child);
+                }
+            } else {
+                self.stack.pop();
+                // the value of a node comes before all its children
+                let res = tree.value().map(|value| (self.path.clone(), value));
+                self.path.pop(tree.prefix().len());
+                if let Some((key, value)) = res {
+                    if below(&key, &self.lower) {
+                        break;
+                    }
+                    if !above(&key, &self.upper) {
+                        return Some((key, value));
+                    }
+                }
+            }
+        }
+        self.stack.clear();
+        None
+    }
+}
+
+struct RadixTreeConverter<K, V>(PhantomData<(K, V)>);
+
+impl<T: AbstractRadixTree<K, V>, K: TKey, V: TValue> Converter<&T, T::Materialized>
+    for RadixTreeConverter<K, V>
+{
+    fn convert(value: &T) -> T::Materialized {
+        materialize(value)
+    }
+}
+
+fn is_subset<K: TKey, V: TValue, W: TValue>(
+    l: &impl AbstractRadixTree<K, V>,
+    r: &impl AbstractRadixTree<K, W>,
+) -> bool {
+    is_subset0(l, l.prefix(), r, r.prefix())
+}
+
+fn is_subset0<K: TKey, V: TValue, W: TValue>(
+    l: &impl AbstractRadixTree<K, V>,
+    l_prefix: &[K],
+    r: &impl AbstractRadixTree<K, W>,
+    r_prefix: &[K],
+) -> bool {
+    let n = common_prefix(l_prefix, r_prefix);
+    if n == l_prefix.len() && n == r_prefix.len() {
+        // prefixes are identical
+        (l.value().is_none() || r.value().is_some())
+            && !BoolOpMergeState::merge(l.children(), r.children(), NonSubsetOp(PhantomData))
+    } else if n == l_prefix.len() {
+        // l is a prefix of r - shorten r_prefix
+        let r_prefix = &r_prefix[n..];
+        // if l has a value but not r, we found one
+        // if one or more of lc are not a subset of r, we are done
+        l.value().is_none()
+            && l.children()
+                .iter()
+                .all(|lc| is_subset0(lc, lc.prefix(), r, r_prefix))
+    } else if n == r_prefix.len() {
+        // r is a prefix of l - shorten L_prefix
+        let l_prefix = &l_prefix[n..];
+        // if l is a subset of none of rc, we are done
+        r.children()
+            .iter()
+            .any(|rc| is_subset0(l, l_prefix, rc, rc.prefix()))
+    } else {
+        // disjoint
+        false
+    }
+}
+
+fn intersects<K: TKey, V: TValue, W: TValue>(
+    l: &impl AbstractRadixTree<K, V>,
+    r: &impl AbstractRadixTree<K, W>,
+) -> bool {
+    intersects0(l, l.prefix(), r, r.prefix())
+}
+
+fn intersects0<K: TKey, V: TValue, W: TValue>(
+    l: &impl AbstractRadixTree<K, V>,
+    l_prefix: &[K],
+    r: &impl AbstractRadixTree<K, W>,
+    r_prefix: &[K],
+) -> bool {
+    let n = common_prefix(l_prefix, r_prefix);
+    if n == l_prefix.len() && n == r_prefix.len() {
+        // prefixes are identical
+        (l.value().is_some() && r.value().is_some())
+            || BoolOpMergeState::merge(l.children(), r.children(), IntersectOp(PhantomData))
+    } else if n == l_prefix.len() {
+        // l is a prefix of r
+        let r_prefix = &r_prefix[n..];
+        l.children()
+            .iter()
+            .any(|lc| intersects0(lc, lc.prefix(), r, r_prefix))
+    } else if n == r_prefix.len() {
+        // r is a prefix of l
+        let l_prefix = &l_prefix[n..];
+        r.children()
+            .iter()
+            .any(|rc| intersects0(l, l_prefix, rc, rc.prefix()))
+    } else {
+        // disjoint
+        false
+    }
+}
+
+/// Outer combine two trees with a function f
+fn outer_combine<
+    K: TKey,
+    V: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R>,
+    A: AbstractRadixTree<K, V, Materialized = R>,
+    B: AbstractRadixTree<K, V, Materialized = R>,
+>(
+    a: &A,
+    b: &B,
+    f: impl Fn(&V, &V) -> Option<V> + Copy,
+) -> R {
+    let n = common_prefix(a.prefix(), b.prefix());
+    let prefix = a.prefix()[..n].into();
+    let mut children = Vec::new();
+    let mut value = None;
+    if n == a.prefix().len() && n == b.prefix().len() {
+        // prefixes are identical
+        value = match (a.value(), b.value()) {
             (Some(a), Some(b)) => f(a, b),
             (Some(a), None) => Some(a.clone()),
             (None, Some(b)) => Some(b.clone()),
@@ -1200,9 +2374,89 @@ fn left_combine<K: TKey, V: TValue, W: TValue, R: AbstractRadixTreeMut<K, V, Mat
     res
 }
 
+/// Join two trees with a function f, producing a tree with a new value type.
+///
+/// Each side is a subtree together with the number of its prefix elements that have already been
+/// consumed. `key` is the key up to the first unconsumed element. `keep` tells if entries that only
+/// exist on the left or right side can produce values at all.
+fn join0<K, V, W, R, A, B, T, F>(
+    key: &mut Vec<K>,
+    a: Option<(&A, usize)>,
+    b: Option<(&B, usize)>,
+    keep: (bool, bool),
+    f: &F,
+) -> T
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    R: TValue,
+    A: AbstractRadixTree<K, V>,
+    B: AbstractRadixTree<K, W>,
+    T: AbstractRadixTreeMut<K, R>,
+    F: Fn(OuterJoinArg<&[K], &V, &W>) -> Option<R>,
+{
+    if (b.is_none() && !keep.0) || (a.is_none() && !keep.1) {
+        return T::empty();
+    }
+    let a_rest = a.map(|(a, o)| &a.prefix()[o..]);
+    let b_rest = b.map(|(b, o)| &b.prefix()[o..]);
+    let n = match (a_rest, b_rest) {
+        (Some(a), Some(b)) => common_prefix(a, b),
+        (Some(a), None) => a.len(),
+        (None, Some(b)) => b.len(),
+        (None, None) => 0,
+    };
+    let prefix = &a_rest.or(b_rest).unwrap_or_default()[..n];
+    key.extend_from_slice(prefix);
+    // the sides that end exactly at this node, and the cursors for the children of this node
+    let a_here = a.filter(|(a, o)| o + n == a.prefix().len()).map(|x| x.0);
+    let b_here = b.filter(|(b, o)| o + n == b.prefix().len()).map(|x| x.0);
+    let value = match (
+        a_here.and_then(|a| a.value()),
+        b_here.and_then(|b| b.value()),
+    ) {
+        (Some(v), Some(w)) => f(OuterJoinArg::Both(key, v, w)),
+        (Some(v), None) => f(OuterJoinArg::Left(key, v)),
+        (None, Some(w)) => f(OuterJoinArg::Right(key, w)),
+        (None, None) => None,
+    };
+    let a_children = match (a_here, a) {
+        (Some(a), _) => a.children().iter().map(|c| (c, 0)).collect(),
+        (None, Some((a, o))) => vec![(a, o + n)],
+        (None, None) => Vec::new(),
+    };
+    let b_children = match (b_here, b) {
+        (Some(b), _) => b.children().iter().map(|c| (c, 0)).collect(),
+        (None, Some((b, o))) => vec![(b, o + n)],
+        (None, None) => Vec::new(),
+    };
+    let mut children = Vec::new();
+    let mut a_iter = a_children.into_iter().peekable();
+    let mut b_iter = b_children.into_iter().peekable();
+    loop {
+        let ordering = match (a_iter.peek(), b_iter.peek()) {
+            (Some((a, ao)), Some((b, bo))) => a.prefix()[*ao].cmp(&b.prefix()[*bo]),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        let child = match ordering {
+            Ordering::Less => join0::<_, _, _, _, _, B, _, _>(key, a_iter.next(), None, keep, f),
+            Ordering::Greater => join0::<_, _, _, _, A, _, _, _>(key, None, b_iter.next(), keep, f),
+            Ordering::Equal => join0(key, a_iter.next(), b_iter.next(), keep, f),
+        };
+        children.push(child);
+    }
+    key.truncate(key.len() - n);
+    let mut res = T::new(prefix.into(), value, children);
+    res.unsplit();
+    res
+}
+
 struct IntersectOp<T>(PhantomData<T>);
 
-impl<'a, K, V, W, I> MergeOperation<I> for IntersectOp<(K, V, W)>
+impl<K, V, W, I> MergeOperation<I> for IntersectOp<(K, V, W)>
 where
     K: TKey,
     V: TValue,
@@ -1230,7 +2484,7 @@ where
 }
 struct NonSubsetOp<V>(PhantomData<V>);
 
-impl<'a, K, V, W, I> MergeOperation<I> for NonSubsetOp<(K, V, W)>
+impl<K, V, W, I> MergeOperation<I> for NonSubsetOp<(K, V, W)>
 where
     K: TKey,
     V: TValue,
@@ -1336,7 +2590,7 @@ where
 /// In place intersection operation
 struct InnerCombineOp<F, P>(F, PhantomData<P>);
 
-impl<'a, K, V, W, F, I, R> MergeOperation<I> for InnerCombineOp<F, (K, V, W)>
+impl<K, V, W, F, I, R> MergeOperation<I> for InnerCombineOp<F, (K, V, W)>
 where
     K: TKey,
     V: TValue,
@@ -1413,7 +2667,7 @@ where
 /// In place intersection operation
 struct LeftCombineOp<F, P>(F, PhantomData<P>);
 
-impl<'a, K, V, W, F, I, R> MergeOperation<I> for LeftCombineOp<F, (K, V, W)>
+impl<K, V, W, F, I, R> MergeOperation<I> for LeftCombineOp<F, (K, V, W)>
 where
     K: TKey,
     V: TValue,
@@ -1490,7 +2744,7 @@ where
 /// Remove prefixes of b in a
 struct RemovePrefixOp<F, P>(F, PhantomData<P>);
 
-impl<'a, K, V, W, F, I, R> MergeOperation<I> for RemovePrefixOp<F, (K, V, W)>
+impl<K, V, W, F, I, R> MergeOperation<I> for RemovePrefixOp<F, (K, V, W)>
 where
     K: TKey,
     V: TValue,
@@ -1524,7 +2778,7 @@ where
 /// Retain prefixes of b in a
 struct RetainPrefixOp<F, P>(F, PhantomData<P>);
 
-impl<'a, K, V, W, F, I, R> MergeOperation<I> for RetainPrefixOp<F, (K, V, W)>
+impl<K, V, W, F, I, R> MergeOperation<I> for RetainPrefixOp<F, (K, V, W)>
 where
     K: TKey,
     V: TValue,
@@ -1555,9 +2809,94 @@ where
     }
 }
 
+fn offset_from<T, U>(base: *const T, p: *const U) -> usize {
+    let base = base as usize;
+    let p = p as usize;
+    assert!(p >= base);
+    p - base
+}
+
+/// Structural debug output, same for all flavours
+fn debug0<K: TKey, V: TValue, T: AbstractRadixTree<K, V> + Debug>(
+    tree: &T,
+    name: &str,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    // strip the generic parameters from the type name
+    let name = name.split('<').next().unwrap_or(name).trim();
+    f.debug_struct(name)
+        .field("prefix", &tree.prefix())
+        .field("value", &tree.value())
+        .field("children", &tree.children())
+        .finish()
+}
+
+/// Equality of two trees of possibly different flavours
+///
+/// Since trees are always in canonical form, structural equality is the same as equality of the mappings.
+fn eq0<K, V, A, B>(a: &A, b: &B) -> bool
+where
+    K: TKey,
+    V: TValue + PartialEq,
+    A: AbstractRadixTree<K, V>,
+    B: AbstractRadixTree<K, V>,
+{
+    let (ac, bc) = (a.children(), b.children());
+    a.prefix() == b.prefix()
+        && a.value() == b.value()
+        && ac.len() == bc.len()
+        // shared children, e.g. in two snapshots of an ArcRadixTree, are equal without looking at them
+        && (std::ptr::eq(ac.as_ptr() as *const u8, bc.as_ptr() as *const u8)
+            || ac.iter().zip(bc).all(|(a, b)| eq0(a, b)))
+}
+
+/// Hash of a tree, same for all flavours
+fn hash0<K, V, T, H>(tree: &T, state: &mut H)
+where
+    K: TKey + Hash,
+    V: TValue + Hash,
+    T: AbstractRadixTree<K, V>,
+    H: Hasher,
+{
+    tree.prefix().hash(state);
+    tree.value().hash(state);
+    state.write_usize(tree.children().len());
+    for child in tree.children() {
+        hash0(child, state);
+    }
+}
+
+fn location<T>(x: &T) -> usize {
+    (x as *const T) as usize
+}
+
+/// Helper to contain an object and an interator that takes the object by reference
+///
+/// This is a quick way to implement into_iter in terms of iter.
+pub struct ObjAndIter<K, V> {
+    k: Box<K>,
+    v: V,
+}
+
+impl<K: 'static, V> ObjAndIter<K, V> {
+    fn new(k: Box<K>, f: impl Fn(&'static K) -> V) -> Self {
+        let kr = unsafe { std::mem::transmute::<&K, &'static K>(k.as_ref()) };
+        let v = f(kr);
+        Self { k, v }
+    }
+}
+
+impl<K: 'static, V: Iterator> Iterator for ObjAndIter<K, V> {
+    type Item = V::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.v.next()
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use std::collections::BTreeSet;
+    use std::collections::{BTreeMap, BTreeSet};
 
     use super::*;
     use maplit::btreeset;
@@ -1744,12 +3083,68 @@ mod test {
             expected == r1
         }
 
-        fn is_disjoint(a: Reference, b: Reference) -> bool {
-            let a1: Test = r2t(&a);
-            let b1: Test = r2t(&b);
-            let actual = a1.is_disjoint(&b1);
-            let expected = a.is_disjoint(&b);
-            expected == actual
+        fn remove(a: Reference, b: Reference) -> bool {
+            let mut r1: Test = r2t(&a);
+            let removed = b.iter().filter(|k| r1.remove(k).is_some()).cloned().collect::<Reference>();
+            let expected = r2t(&a.difference(&b).cloned().collect());
+            // the result must be canonical, so structural equality must hold
+            expected == r1 && removed == a.intersection(&b).cloned().collect::<Reference>()
+        }
+
+        fn remove_prefix_single(a: Reference, b: Reference) -> bool {
+            let mut r1: Test = r2t(&a);
+            for prefix in b.iter() {
+                r1.remove_prefix(prefix);
+            }
+            let mut r = a;
+            r.retain(|re| !b.iter().any(|x| re.starts_with(x)));
+            r2t(&r) == r1
+        }
+
+        fn longest_prefix_match(a: Reference, b: Vec<u8>) -> bool {
+            let a1: Test = r2t(&a);
+            let expected = a.iter().filter(|x| b.starts_with(x)).map(|x| x.len()).collect::<Vec<_>>();
+            let actual = a1.prefixes_of(&b).map(|(n, _)| n).collect::<Vec<_>>();
+            expected == actual && a1.longest_prefix_match(&b).map(|(n, _)| n) == expected.last().cloned()
+        }
+
+        fn range(a: Reference, x: Vec<u8>, y: Vec<u8>) -> bool {
+            let (x, y) = if x <= y { (x, y) } else { (y, x) };
+            let a1: Test = r2t(&a);
+            let mut bounds = vec![
+                (Bound::Included(x.clone()), Bound::Included(y.clone())),
+                (Bound::Included(x.clone()), Bound::Excluded(y.clone())),
+                (Bound::Excluded(x.clone()), Bound::Included(y.clone())),
+                (Bound::Unbounded, Bound::Excluded(y.clone())),
+                (Bound::Excluded(x.clone()), Bound::Unbounded),
+                (Bound::Unbounded, Bound::Unbounded),
+            ];
+            if x != y {
+                bounds.push((Bound::Excluded(x), Bound::Excluded(y)));
+            }
+            bounds.into_iter().all(|range| {
+                let expected = a.range(range.clone()).cloned().collect::<Vec<_>>();
+                let actual = a1.range(range.clone()).map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+                let mut actual_rev = a1.range_rev(range).map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+                actual_rev.reverse();
+                expected == actual && expected == actual_rev
+            })
+        }
+
+        fn first_last(a: Reference) -> bool {
+            let a1: Test = r2t(&a);
+            a1.first().map(|(k, _)| k.to_vec()).as_ref() == a.iter().next()
+                && a1.last().map(|(k, _)| k.to_vec()).as_ref() == a.iter().next_back()
+                && a1.iter_rev().map(|(k, _)| k.to_vec()).collect::<Vec<_>>()
+                    == a.iter().rev().cloned().collect::<Vec<_>>()
+        }
+
+        fn is_disjoint(a: Reference, b: Reference) -> bool {
+            let a1: Test = r2t(&a);
+            let b1: Test = r2t(&b);
+            let actual = a1.is_disjoint(&b1);
+            let expected = a.is_disjoint(&b);
+            expected == actual
         }
 
         fn is_subset(a: Reference, b: Reference) -> bool {
@@ -1868,6 +3263,1216 @@ mod test {
         assert_eq!(test, expected);
     }
 
+    #[test]
+    fn longest_prefix_match_sample() {
+        let routes = [("10.", 1), ("10.1.", 2), ("10.1.2.", 3), ("192.168.", 4)]
+            .iter()
+            .map(|(k, v)| (k.as_bytes(), *v))
+            .collect::<RadixTree<u8, i32>>();
+        assert_eq!(routes.longest_prefix_match(b"10.1.2.3"), Some((7, &3)));
+        assert_eq!(routes.longest_prefix_match(b"10.1.3.4"), Some((5, &2)));
+        assert_eq!(routes.longest_prefix_match(b"10.2.3.4"), Some((3, &1)));
+        assert_eq!(routes.longest_prefix_match(b"11.0.0.1"), None);
+        let all = routes.prefixes_of(b"10.1.2.3").collect::<Vec<_>>();
+        assert_eq!(all, vec![(3, &1), (5, &2), (7, &3)]);
+    }
+
+    fn insert_all(pairs: &[(Vec<u8>, u8)], keep: Keep) -> RadixTree<u8, u8> {
+        let mut res = RadixTree::default();
+        for (k, v) in pairs {
+            if keep == Keep::Last || !res.contains_key(k) {
+                res.insert(k, *v);
+            }
+        }
+        res
+    }
+
+    #[quickcheck]
+    fn from_sorted_iter(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let pairs = a.into_iter().collect::<Vec<_>>();
+        let expected = insert_all(&pairs, Keep::Last);
+        let actual = RadixTree::from_sorted_iter(pairs, Keep::Last);
+        // the structure must be identical, not just the content
+        expected == actual
+    }
+
+    #[quickcheck]
+    fn from_unsorted_iter(pairs: Vec<(Vec<u8>, u8)>) -> bool {
+        let first = RadixTree::from_unsorted_iter(pairs.clone(), Keep::First);
+        let last = RadixTree::from_unsorted_iter(pairs.clone(), Keep::Last);
+        let collected = pairs.iter().cloned().collect::<RadixTree<u8, u8>>();
+        first == insert_all(&pairs, Keep::First)
+            && last == insert_all(&pairs, Keep::Last)
+            && collected == last
+    }
+
+    fn map_tree(map: &BTreeMap<Vec<u8>, u8>) -> RadixTree<u8, u8> {
+        RadixTree::from_sorted_iter(map.iter().map(|(k, v)| (k, *v)), Keep::Last)
+    }
+
+    #[quickcheck]
+    fn get_mut(a: BTreeMap<Vec<u8>, u8>, b: Vec<Vec<u8>>) -> bool {
+        let mut tree = map_tree(&a);
+        let mut expected = a;
+        for key in b {
+            if let Some(value) = tree.get_mut(&key) {
+                *value = value.wrapping_add(1);
+            }
+            if let Some(value) = expected.get_mut(&key) {
+                *value = value.wrapping_add(1);
+            }
+        }
+        tree == map_tree(&expected)
+    }
+
+    #[quickcheck]
+    fn iter_mut(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let mut t1 = map_tree(&a);
+        let mut t2 = t1.clone();
+        for (k, v) in t1.iter_mut() {
+            *v = v.wrapping_add(k.len() as u8);
+        }
+        for v in t2.values_mut() {
+            *v = v.wrapping_add(1);
+        }
+        let e1 = a
+            .iter()
+            .map(|(k, v)| (k.clone(), v.wrapping_add(k.len() as u8)))
+            .collect();
+        let e2 = a
+            .iter()
+            .map(|(k, v)| (k.clone(), v.wrapping_add(1)))
+            .collect();
+        t1 == map_tree(&e1) && t2 == map_tree(&e2)
+    }
+
+    #[quickcheck]
+    fn retain(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let mut tree = map_tree(&a);
+        tree.retain(|k, v| (k.len() + *v as usize) % 3 < 2);
+        let mut expected = a;
+        expected.retain(|k, v| (k.len() + *v as usize) % 3 < 2);
+        tree == map_tree(&expected)
+    }
+
+    #[quickcheck]
+    fn map_values(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let tree = map_tree(&a);
+        let mapped: RadixTree<u8, u16> = tree.map_values(|v| *v as u16 * 2);
+        let filtered: RadixTree<u8, u8> =
+            tree.filter_map_values(|v| if v % 2 == 0 { Some(v / 2) } else { None });
+        let e1 = a.iter().map(|(k, v)| (k, *v as u16 * 2));
+        let e2 = a
+            .iter()
+            .filter(|(_, v)| *v % 2 == 0)
+            .map(|(k, v)| (k, v / 2));
+        mapped == RadixTree::from_sorted_iter(e1, Keep::Last)
+            && filtered == RadixTree::from_sorted_iter(e2, Keep::Last)
+    }
+
+    #[quickcheck]
+    fn joins(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        // use a small key alphabet so the two trees overlap
+        let small = |k: Vec<u8>| k.into_iter().map(|x| x % 4).collect::<Vec<_>>();
+        let a = a
+            .into_iter()
+            .map(|(k, v)| (small(k), v))
+            .collect::<BTreeMap<_, _>>();
+        let b = b
+            .into_iter()
+            .map(|(k, v)| (small(k), v as u16))
+            .collect::<BTreeMap<_, _>>();
+        let ta = map_tree(&a);
+        let tb = RadixTree::from_sorted_iter(b.iter().map(|(k, v)| (k, *v)), Keep::Last);
+        // drop some of the results to check that dropping works
+        let keep = |k: &[u8]| k.len() % 3 < 2;
+        // the values contain the key, to check that the closure gets the right key
+        type Joined<V, W> = RadixTree<u8, (Vec<u8>, V, W)>;
+        let outer: Joined<Option<u8>, Option<u16>> = ta.outer_join(&tb, |arg| {
+            let (k, v, w) = match arg {
+                OuterJoinArg::Left(k, v) => (k, Some(*v), None),
+                OuterJoinArg::Right(k, w) => (k, None, Some(*w)),
+                OuterJoinArg::Both(k, v, w) => (k, Some(*v), Some(*w)),
+            };
+            Some((k.to_vec(), v, w)).filter(|_| keep(k))
+        });
+        let left: Joined<u8, Option<u16>> = ta.left_join(&tb, |k, v, w| {
+            Some((k.to_vec(), *v, w.cloned())).filter(|_| keep(k))
+        });
+        let inner: Joined<u8, u16> = ta.inner_join(&tb, |k, v, w| {
+            Some((k.to_vec(), *v, *w)).filter(|_| keep(k))
+        });
+        let keys = a.keys().chain(b.keys()).collect::<BTreeSet<_>>();
+        let e_outer = keys
+            .iter()
+            .filter(|k| keep(k))
+            .map(|k| (*k, (k.to_vec(), a.get(*k).cloned(), b.get(*k).cloned())));
+        let e_left = a
+            .iter()
+            .filter(|(k, _)| keep(k))
+            .map(|(k, v)| (k, (k.clone(), *v, b.get(k).cloned())));
+        let e_inner = a
+            .iter()
+            .filter(|(k, _)| keep(k))
+            .filter_map(|(k, v)| Some((k, (k.clone(), *v, *b.get(k)?))));
+        outer == RadixTree::from_sorted_iter(e_outer, Keep::Last)
+            && left == RadixTree::from_sorted_iter(e_left, Keep::Last)
+            && inner == RadixTree::from_sorted_iter(e_inner, Keep::Last)
+    }
+
+    #[test]
+    fn get_mut_retain_arc() {
+        let a = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let snapshot = arc_tree(&a);
+        let arcs = |tree: &ArcRadixTree<u8, u8>| {
+            let mut arcs = BTreeMap::new();
+            tree.all_arcs(&mut arcs);
+            arcs.keys().cloned().collect::<BTreeSet<_>>()
+        };
+        let before = arcs(&snapshot);
+        let mut tree = snapshot.clone();
+        // a miss must not copy anything
+        assert!(tree.get_mut(b"x").is_none());
+        assert_eq!(arcs(&tree), before);
+        *tree.get_mut(b"123").unwrap() = 0;
+        assert_eq!(tree.get(b"123"), Some(&0));
+        // only the path to the changed value is copied
+        assert!(arcs(&tree).difference(&before).count() <= 4);
+        let mut tree = snapshot.clone();
+        tree.retain(|k, _| k != b"999");
+        assert_eq!(tree.iter().count(), 999);
+        assert!(arcs(&tree).difference(&before).count() <= 4);
+        // the snapshot is unchanged
+        assert_eq!(snapshot.get(b"123"), Some(&123));
+        assert_eq!(snapshot.iter().count(), 1000);
+    }
+
+    #[test]
+    fn from_sorted_iter_duplicates() {
+        let pairs = vec![("", 0), ("a", 1), ("a", 2), ("ab", 3), ("ab", 4), ("b", 5)];
+        let first = RadixTree::from_sorted_iter(pairs.clone(), Keep::First);
+        let last = RadixTree::from_sorted_iter(pairs, Keep::Last);
+        let values = |tree: &RadixTree<u8, u8>| tree.values().cloned().collect::<Vec<_>>();
+        assert_eq!(values(&first), vec![0, 1, 3, 5]);
+        assert_eq!(values(&last), vec![0, 2, 4, 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "keys must be sorted")]
+    fn from_sorted_iter_unsorted() {
+        RadixTree::<u8, ()>::from_sorted_iter(vec![("b", ()), ("a", ())], Keep::Last);
+    }
+
+    #[test]
+    fn from_sorted_iter_arc() {
+        let keys = (0..1000u32).map(|i| i.to_string()).collect::<BTreeSet<_>>();
+        let tree = ArcRadixTree::from_sorted_iter(keys.iter().map(|k| (k, ())), Keep::Last);
+        let actual = tree
+            .iter()
+            .map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(actual, keys.into_iter().collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "lazy_radixtree")]
+    #[test]
+    fn from_sorted_iter_lazy() {
+        let keys = (0..1000u32).map(|i| i.to_string()).collect::<BTreeSet<_>>();
+        let tree: LazyRadixTree<u8, ()> = keys.iter().map(|k| (k, ())).collect();
+        let actual = tree
+            .iter()
+            .map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(actual, keys.into_iter().collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "serde")]
+    #[quickcheck]
+    fn serde_flat(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let tree = map_tree(&a);
+        let bytes = bincode::serialize(&tree).unwrap();
+        let flat: Vec<(Vec<u8>, u8)> = bincode::deserialize(&bytes).unwrap();
+        let tree1: RadixTree<u8, u8> = bincode::deserialize(&bytes).unwrap();
+        flat == a.into_iter().collect::<Vec<_>>() && tree1 == tree
+    }
+
+    #[cfg(feature = "serde")]
+    #[quickcheck]
+    fn serde_flat_json(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let tree = map_tree(&a);
+        let json = serde_json::to_string(&tree).unwrap();
+        let tree1: RadixTree<u8, u8> = serde_json::from_str(&json).unwrap();
+        tree1 == tree
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_flat_json_unsorted() {
+        let tree: RadixTree<u8, u8> = serde_json::from_str("[[[2],1],[[1,2],2],[[2],3]]").unwrap();
+        let expected: RadixTree<u8, u8> = vec![(vec![1, 2], 2), (vec![2], 3)].into_iter().collect();
+        assert_eq!(tree, expected);
+        assert_eq!(serde_json::to_string(&tree).unwrap(), "[[[1,2],2],[[2],3]]");
+    }
+
+    #[cfg(all(feature = "serde", feature = "lazy_radixtree"))]
+    #[quickcheck]
+    fn serde_flat_arc_lazy(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let tree = arc_tree(&a);
+        let bytes = bincode::serialize(&tree).unwrap();
+        let arc: ArcRadixTree<u8, u8> = bincode::deserialize(&bytes).unwrap();
+        let lazy: LazyRadixTree<u8, u8> = bincode::deserialize(&bytes).unwrap();
+        let expected = a.into_iter().collect::<Vec<_>>();
+        arc.iter()
+            .map(|(k, v)| (k.to_vec(), *v))
+            .collect::<Vec<_>>()
+            == expected
+            && lazy
+                .iter()
+                .map(|(k, v)| (k.to_vec(), *v))
+                .collect::<Vec<_>>()
+                == expected
+            && bincode::serialize(&lazy).unwrap() == bytes
+    }
+
+    #[cfg(feature = "serde")]
+    #[quickcheck]
+    fn serde_structural(a: BTreeMap<Vec<u8>, u8>) -> bool {
+        let tree = map_tree(&a);
+        let json = structural::serialize(&tree, serde_json::value::Serializer).unwrap();
+        let tree1: RadixTree<u8, u8> = structural::deserialize(json.clone()).unwrap();
+        let mut bytes = Vec::new();
+        structural::serialize(
+            &tree,
+            &mut bincode::Serializer::new(&mut bytes, bincode::DefaultOptions::new()),
+        )
+        .unwrap();
+        let tree2: RadixTree<u8, u8> = structural::deserialize(
+            &mut bincode::Deserializer::from_slice(&bytes, bincode::DefaultOptions::new()),
+        )
+        .unwrap();
+        tree1 == tree && tree2 == tree
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_structural_non_canonical() {
+        let parse = |text: &str| -> Result<RadixTree<u8, u8>, _> {
+            structural::deserialize(serde_json::from_str::<serde_json::Value>(text).unwrap())
+        };
+        assert!(parse("[[], null, []]").unwrap().is_empty());
+        assert!(parse("[[1], 2, [[[2], 3, []], [[3], 4, []]]]").is_ok());
+        // empty tree with prefix
+        assert!(parse("[[1], null, []]").is_err());
+        // children not sorted
+        assert!(parse("[[1], 2, [[[3], 3, []], [[2], 4, []]]]").is_err());
+        // child with empty prefix
+        assert!(parse("[[1], 2, [[[], 3, []]]]").is_err());
+        // node without value with a single child
+        assert!(parse("[[1], null, [[[2], 3, []]]]").is_err());
+        // empty child
+        assert!(parse("[[1], 2, [[[2], null, []]]]").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[quickcheck]
+    fn serde_shared(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        let snapshot = arc_tree(&a);
+        let mut tree = snapshot.clone();
+        for (k, v) in &b {
+            tree.insert(k, *v);
+        }
+        let trees = vec![snapshot, tree];
+        let json = shared::serialize(&trees, serde_json::value::Serializer).unwrap();
+        let trees1: Vec<ArcRadixTree<u8, u8>> = shared::deserialize(json).unwrap();
+        let pairs = |tree: &ArcRadixTree<u8, u8>| {
+            tree.iter()
+                .map(|(k, v)| (k.to_vec(), *v))
+                .collect::<Vec<_>>()
+        };
+        trees1.len() == 2
+            && pairs(&trees1[0]) == pairs(&trees[0])
+            && pairs(&trees1[1]) == pairs(&trees[1])
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_shared_snapshots() {
+        let a = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let snapshot = arc_tree(&a);
+        let mut tree = snapshot.clone();
+        tree.insert(b"123", 0);
+        let trees = vec![snapshot, tree];
+        let single = serde_json::to_string(
+            &structural::serialize(&trees[0], serde_json::value::Serializer).unwrap(),
+        )
+        .unwrap();
+        let json = serde_json::to_string(
+            &shared::serialize(&trees, serde_json::value::Serializer).unwrap(),
+        )
+        .unwrap();
+        // the second snapshot only adds the path to the changed value
+        assert!(json.len() < single.len() * 5 / 4);
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        let trees1: Vec<ArcRadixTree<u8, u8>> = shared::deserialize(value).unwrap();
+        let arcs = |tree: &ArcRadixTree<u8, u8>| {
+            let mut arcs = BTreeMap::new();
+            tree.all_arcs(&mut arcs);
+            arcs.keys().cloned().collect::<BTreeSet<_>>()
+        };
+        // the deserialized snapshots share all but the changed path
+        let (a0, a1) = (arcs(&trees1[0]), arcs(&trees1[1]));
+        assert!(a1.difference(&a0).count() <= 4);
+        assert_eq!(trees1[1].get(b"123"), Some(&0));
+        assert_eq!(trees1[0].get(b"123"), Some(&123));
+        // references must point to earlier lists
+        let parse = |text: &str| -> Result<Vec<ArcRadixTree<u8, u8>>, _> {
+            shared::deserialize(serde_json::from_str::<serde_json::Value>(text).unwrap())
+        };
+        assert!(parse("[[[[[1], 1, null], [[2], 2, null]]], [[[], null, 0]]]").is_ok());
+        assert!(parse("[[[[[1], 1, null], [[2], 2, 0]]], [[[], null, 0]]]").is_err());
+        assert!(parse("[[], [[[], null, 0]]]").is_err());
+    }
+
+    #[test]
+    fn range_sample() {
+        let tree = test_tree(&["a", "ab", "abc", "abd", "b", "ba", "c"]);
+        let keys = |iter: &mut dyn Iterator<Item = (IterKey<u8>, &())>| {
+            iter.map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys(&mut tree.range("ab".."b")), ["ab", "abc", "abd"]);
+        assert_eq!(keys(&mut tree.range("ab"..="b")), ["ab", "abc", "abd", "b"]);
+        assert_eq!(
+            keys(&mut tree.range("abc"..)),
+            ["abc", "abd", "b", "ba", "c"]
+        );
+        assert_eq!(keys(&mut tree.range_rev(.."abd")), ["abc", "ab", "a"]);
+        assert_eq!(keys(&mut tree.range_rev("b"..)), ["c", "ba", "b"]);
+    }
+
+    #[test]
+    fn remove_sample() {
+        let mut test = test_tree(&["a", "ab", "abc", "abd", "b"]);
+        assert_eq!(test.remove(b"ab"), Some(()));
+        assert_eq!(test.remove(b"ab"), None);
+        assert_eq!(test.remove(b"x"), None);
+        assert_eq!(test, test_tree(&["a", "abc", "abd", "b"]));
+        assert_eq!(test.remove(b"abc"), Some(()));
+        // "abd" must be merged back into a single node
+        assert_eq!(test, test_tree(&["a", "abd", "b"]));
+        test.remove_prefix(b"ab");
+        assert_eq!(test, test_tree(&["a", "b"]));
+        test.remove_prefix(b"");
+        assert!(test.is_empty());
+        assert_eq!(test, RadixTree::default());
+    }
+
+    fn arc_tree(map: &BTreeMap<Vec<u8>, u8>) -> ArcRadixTree<u8, u8> {
+        let mut res = ArcRadixTree::default();
+        for (k, v) in map {
+            res.insert(k, *v);
+        }
+        res
+    }
+
+    fn map_diff(a: &BTreeMap<Vec<u8>, u8>, b: &BTreeMap<Vec<u8>, u8>) -> Vec<Change<u8, u8>> {
+        let keys = a.keys().chain(b.keys()).collect::<BTreeSet<_>>();
+        keys.into_iter()
+            .filter_map(|key| match (a.get(key), b.get(key)) {
+                (Some(old), Some(new)) if old != new => Some(Change::Update {
+                    key: key.clone(),
+                    old: *old,
+                    new: *new,
+                }),
+                (Some(value), None) => Some(Change::Delete {
+                    key: key.clone(),
+                    value: *value,
+                }),
+                (None, Some(value)) => Some(Change::Insert {
+                    key: key.clone(),
+                    value: *value,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[quickcheck]
+    fn arc_diff(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        arc_tree(&a).diff(&arc_tree(&b)) == map_diff(&a, &b)
+    }
+
+    #[quickcheck]
+    fn arc_diff_shared(a: BTreeMap<Vec<u8>, u8>, changes: Vec<(Vec<u8>, Option<u8>)>) -> bool {
+        let t0 = arc_tree(&a);
+        let mut t1 = t0.clone();
+        let mut b = a.clone();
+        for (k, v) in changes {
+            if let Some(v) = v {
+                t1.insert(&k, v);
+                b.insert(k, v);
+            } else {
+                t1.remove(&k);
+                b.remove(&k);
+            }
+        }
+        t0.diff(&t1) == map_diff(&a, &b) && t1.diff(&t0) == map_diff(&b, &a)
+    }
+
+    #[test]
+    fn arc_diff_sample() {
+        let a = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let t0 = arc_tree(&a);
+        let mut t1 = t0.clone();
+        assert!(t0.diff(&t1).is_empty());
+        t1.insert(b"123", 0);
+        t1.insert(b"1234", 1);
+        t1.remove(b"999");
+        assert_eq!(
+            t0.diff(&t1),
+            vec![
+                Change::Update {
+                    key: b"123".to_vec(),
+                    old: 123,
+                    new: 0
+                },
+                Change::Insert {
+                    key: b"1234".to_vec(),
+                    value: 1
+                },
+                Change::Delete {
+                    key: b"999".to_vec(),
+                    value: (999 % 256) as u8
+                },
+            ]
+        );
+    }
+
+    /// conflict resolution for the merge tests, owning the data so conflicts can be compared
+    fn merge_resolve(
+        conflict: MergeConflict<&[u8], &u8>,
+    ) -> (MergeConflict<Vec<u8>, u8>, Option<u8>) {
+        match conflict {
+            MergeConflict::BothAdded(k, o, t) => (
+                MergeConflict::BothAdded(k.to_vec(), *o, *t),
+                Some(o.max(t).wrapping_add(1)),
+            ),
+            MergeConflict::BothModified(k, b, o, t) => (
+                MergeConflict::BothModified(k.to_vec(), *b, *o, *t),
+                Some(b ^ o ^ t),
+            ),
+            MergeConflict::ModifiedDeleted(k, b, o) => {
+                (MergeConflict::ModifiedDeleted(k.to_vec(), *b, *o), None)
+            }
+            MergeConflict::DeletedModified(k, b, t) => {
+                (MergeConflict::DeletedModified(k.to_vec(), *b, *t), Some(*t))
+            }
+        }
+    }
+
+    fn apply_changes(
+        tree: &mut ArcRadixTree<u8, u8>,
+        map: &mut BTreeMap<Vec<u8>, u8>,
+        changes: Vec<(Vec<u8>, Option<u8>)>,
+    ) {
+        for (k, v) in changes {
+            if let Some(v) = v {
+                tree.insert(&k, v);
+                map.insert(k, v);
+            } else {
+                tree.remove(&k);
+                map.remove(&k);
+            }
+        }
+    }
+
+    #[quickcheck]
+    fn three_way_merge(
+        base: BTreeMap<Vec<u8>, u8>,
+        ours: Vec<(Vec<u8>, Option<u8>)>,
+        theirs: Vec<(Vec<u8>, Option<u8>)>,
+    ) -> bool {
+        let t0 = arc_tree(&base);
+        let (mut t1, mut t2) = (t0.clone(), t0.clone());
+        let (mut m1, mut m2) = (base.clone(), base.clone());
+        apply_changes(&mut t1, &mut m1, ours);
+        apply_changes(&mut t2, &mut m2, theirs);
+        let conflicts = std::cell::RefCell::new(Vec::new());
+        let merged = ArcRadixTree::three_way_merge(&t0, &t1, &t2, |c| {
+            let (c, v) = merge_resolve(c);
+            conflicts.borrow_mut().push(c);
+            v
+        });
+        // the same merge, key by key
+        let keys = base
+            .keys()
+            .chain(m1.keys())
+            .chain(m2.keys())
+            .collect::<BTreeSet<_>>();
+        let mut expected = BTreeMap::new();
+        let mut expected_conflicts = Vec::new();
+        for key in keys {
+            let (b, o, t) = (base.get(key), m1.get(key), m2.get(key));
+            let value = if o == t || t == b {
+                o.cloned()
+            } else if o == b {
+                t.cloned()
+            } else {
+                let (c, v) = merge_resolve(match (b, o, t) {
+                    (None, Some(o), Some(t)) => MergeConflict::BothAdded(key, o, t),
+                    (Some(b), Some(o), Some(t)) => MergeConflict::BothModified(key, b, o, t),
+                    (Some(b), Some(o), None) => MergeConflict::ModifiedDeleted(key, b, o),
+                    (Some(b), None, Some(t)) => MergeConflict::DeletedModified(key, b, t),
+                    _ => unreachable!(),
+                });
+                expected_conflicts.push(c);
+                v
+            };
+            if let Some(value) = value {
+                expected.insert(key.clone(), value);
+            }
+        }
+        merged == arc_tree(&expected) && conflicts.into_inner() == expected_conflicts
+    }
+
+    #[test]
+    fn three_way_merge_sample() {
+        let base = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let t0 = arc_tree(&base);
+        let mut ours = t0.clone();
+        let mut theirs = t0.clone();
+        ours.insert(b"123", 1);
+        ours.insert(b"555", 1);
+        theirs.insert(b"123", 2);
+        theirs.remove(b"999");
+        let conflicts = std::cell::RefCell::new(Vec::new());
+        let merged = ArcRadixTree::three_way_merge(&t0, &ours, &theirs, |c| {
+            let (c, v) = merge_resolve(c);
+            conflicts.borrow_mut().push(c);
+            v
+        });
+        assert_eq!(
+            conflicts.into_inner(),
+            vec![MergeConflict::BothModified(b"123".to_vec(), 123, 1, 2)]
+        );
+        assert_eq!(merged.get(b"123"), Some(&(123 ^ 1 ^ 2)));
+        assert_eq!(merged.get(b"555"), Some(&1));
+        assert_eq!(merged.get(b"999"), None);
+        assert_eq!(merged.iter().count(), 999);
+        // subtrees that were only changed on one side are shared with that side
+        let child = |tree: &ArcRadixTree<u8, u8>, i: usize| tree.children()[i].children().as_ptr();
+        assert_eq!(child(&merged, 5), child(&ours, 5));
+        assert_eq!(child(&merged, 9), child(&theirs, 9));
+        assert_eq!(child(&merged, 0), child(&t0, 0));
+        assert_ne!(child(&merged, 1), child(&ours, 1));
+    }
+
+    fn levenshtein(a: &[u8], b: &[u8]) -> usize {
+        let mut row = (0..=b.len()).collect::<Vec<_>>();
+        for (i, x) in a.iter().enumerate() {
+            let mut prev = row[0];
+            row[0] = i + 1;
+            for (j, y) in b.iter().enumerate() {
+                let next = (prev + usize::from(x != y))
+                    .min(row[j] + 1)
+                    .min(row[j + 1] + 1);
+                prev = row[j + 1];
+                row[j + 1] = next;
+            }
+        }
+        row[b.len()]
+    }
+
+    #[quickcheck]
+    fn fuzzy_search(a: BTreeMap<Vec<u8>, u8>, key: Vec<u8>, max_distance: u8) -> bool {
+        // use a small alphabet so there are matches
+        let small = |k: &[u8]| k.iter().map(|x| x % 4).collect::<Vec<_>>();
+        let a = a
+            .into_iter()
+            .map(|(k, v)| (small(&k), v))
+            .collect::<BTreeMap<_, _>>();
+        let key = small(&key);
+        let max_distance = (max_distance % 4) as usize;
+        let tree = map_tree(&a);
+        let expected = a
+            .iter()
+            .map(|(k, v)| (k.clone(), *v, levenshtein(k, &key)))
+            .filter(|(_, _, d)| *d <= max_distance)
+            .collect::<Vec<_>>();
+        let actual = tree
+            .fuzzy_search(&key, max_distance)
+            .map(|(k, v, d)| (k.to_vec(), *v, d))
+            .collect::<Vec<_>>();
+        expected == actual
+    }
+
+    #[test]
+    fn fuzzy_search_sample() {
+        let tree = test_tree(&["apple", "apply", "ape", "maple", "applesauce", "banana"]);
+        let search = |key: &str, d: usize| {
+            tree.fuzzy_search(key.as_bytes(), d)
+                .map(|(k, _, d)| (String::from_utf8(k.to_vec()).unwrap(), d))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(search("apple", 0), vec![("apple".to_string(), 0)]);
+        assert_eq!(
+            search("appel", 2),
+            vec![
+                ("ape".to_string(), 2),
+                ("apple".to_string(), 2),
+                ("apply".to_string(), 2),
+            ]
+        );
+        assert_eq!(
+            search("aple", 1),
+            vec![
+                ("ape".to_string(), 1),
+                ("apple".to_string(), 1),
+                ("maple".to_string(), 1),
+            ]
+        );
+        assert!(search("xyz", 2).is_empty());
+        assert_eq!(search("", 3), vec![("ape".to_string(), 3)]);
+    }
+
+    #[quickcheck]
+    fn matches(a: BTreeMap<Vec<u8>, u8>, pattern: Vec<u8>) -> bool {
+        // use a small alphabet so there are matches
+        let a = a
+            .into_iter()
+            .map(|(k, v)| (k.iter().map(|x| b"abc"[*x as usize % 3]).collect(), v))
+            .collect::<BTreeMap<Vec<u8>, _>>();
+        let pattern = pattern
+            .iter()
+            .map(|x| b"abc?*"[*x as usize % 5])
+            .collect::<Vec<_>>();
+        let pattern = Pattern::glob(&pattern);
+        let tree = map_tree(&a);
+        let expected = a
+            .iter()
+            .filter(|(k, _)| pattern.is_match(k))
+            .map(|(k, v)| (k.clone(), *v))
+            .collect::<Vec<_>>();
+        let actual = tree
+            .matches(&pattern)
+            .map(|(k, v)| (k.to_vec(), *v))
+            .collect::<Vec<_>>();
+        expected == actual
+    }
+
+    #[test]
+    fn matches_sample() {
+        let tree = test_tree(&[
+            "user/alice/settings",
+            "user/alice/profile",
+            "user/bob/settings",
+            "user/bob/settings/old",
+            "users/carol/settings",
+            "group/admins/settings",
+        ]);
+        let matches = |glob: &str| {
+            tree.matches(&Pattern::glob(glob.as_bytes()))
+                .map(|(k, _)| String::from_utf8(k.to_vec()).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            matches("user/*/settings"),
+            vec!["user/alice/settings", "user/bob/settings"]
+        );
+        assert_eq!(matches("user?/*/settings"), vec!["users/carol/settings"]);
+        assert_eq!(
+            matches("*/[a-b]*/settings*"),
+            vec![
+                "group/admins/settings",
+                "user/alice/settings",
+                "user/bob/settings",
+                "user/bob/settings/old"
+            ]
+        );
+        assert_eq!(matches("[!u]*").len(), 1);
+        assert!(matches("user").is_empty());
+        let arc = ArcRadixTree::from(tree.clone());
+        assert_eq!(arc.matches(&Pattern::glob(b"user/*/settings")).count(), 2);
+    }
+
+    type Summarized = SummaryRadixTree<u8, u8, (Count, (Max, Min))>;
+
+    /// summary of the values of a map, to compare with the cached summary
+    fn map_summary<'a>(values: impl Iterator<Item = &'a u8>) -> (usize, (Option<u8>, Option<u8>)) {
+        let values = values.cloned().collect::<Vec<_>>();
+        (
+            values.len(),
+            (values.iter().max().cloned(), values.iter().min().cloned()),
+        )
+    }
+
+    #[quickcheck]
+    fn summary(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>, prefix: Vec<u8>) -> bool {
+        let mut tree: Summarized = a.clone().into_iter().collect();
+        let mut res = *tree.summary() == map_summary(a.values());
+        let bt: Summarized = b.clone().into_iter().collect();
+        // all combine operations must keep the summary up to date
+        let mut reference = a.clone();
+        reference.extend(b.clone());
+        tree.outer_combine_with(&bt, |v, w| {
+            *v = *w;
+            true
+        });
+        res &= *tree.summary() == map_summary(reference.values());
+        reference.retain(|k, _| !b.contains_key(k));
+        tree.difference_with(&bt);
+        res &= *tree.summary() == map_summary(reference.values());
+        for (k, v) in tree.iter_mut() {
+            *v = v.wrapping_add(k.len() as u8);
+        }
+        for (k, v) in reference.iter_mut() {
+            *v = v.wrapping_add(k.len() as u8);
+        }
+        res &= *tree.summary() == map_summary(reference.values());
+        if let Some(k) = a.keys().next() {
+            tree.remove(k);
+            reference.remove(k);
+            res &= *tree.summary() == map_summary(reference.values());
+        }
+        if let Some(k) = a.keys().last() {
+            tree.insert(&prefix, 7);
+            reference.insert(prefix.clone(), 7);
+            res &= *tree.summary() == map_summary(reference.values());
+            if let Some(v) = tree.get_mut(k) {
+                *v = 255;
+                *reference.get_mut(k).unwrap() = 255;
+            }
+            res &= *tree.summary() == map_summary(reference.values());
+        }
+        res &= tree.summary_prefix(&prefix)
+            == map_summary(
+                reference
+                    .iter()
+                    .filter(|(k, _)| k.starts_with(&prefix))
+                    .map(|(_, v)| v),
+            );
+        res && tree
+            .iter()
+            .map(|(k, v)| (k.to_vec(), *v))
+            .eq(reference.into_iter())
+    }
+
+    #[quickcheck]
+    fn nth_rank(a: BTreeMap<Vec<u8>, u8>, keys: Vec<Vec<u8>>) -> bool {
+        let tree: SummaryRadixTree<u8, u8, Count> = a.clone().into_iter().collect();
+        let entries = a.into_iter().collect::<Vec<_>>();
+        let mut res = tree.count() == entries.len();
+        for (i, (k, v)) in entries.iter().enumerate() {
+            res &= tree.nth(i).map(|(k, v)| (k.to_vec(), *v)) == Some((k.clone(), *v));
+            res &= tree.rank(k) == i;
+        }
+        res &= tree.nth(entries.len()).is_none();
+        for key in keys.iter().chain(entries.iter().map(|(k, _)| k)) {
+            let expected = entries.iter().filter(|(k, _)| k < key).count();
+            res &= tree.rank(key) == expected;
+        }
+        res
+    }
+
+    #[test]
+    fn summary_sample() {
+        let mut tree: SummaryRadixTree<u8, u64, (Count, Sum)> = vec![
+            ("user/alice/age", 30u64),
+            ("user/bob/age", 40),
+            ("user/carol/age", 50),
+            ("group/admins/size", 2),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(*tree.summary(), (4, 122));
+        assert_eq!(tree.summary_prefix(b"user/"), (3, 120));
+        assert_eq!(tree.summary_prefix(b"user/b"), (1, 40));
+        assert_eq!(tree.summary_prefix(b"nobody"), (0, 0));
+        tree.remove_prefix(b"user/a");
+        assert_eq!(tree.summary_prefix(b"user/"), (2, 90));
+        assert_eq!(
+            tree.nth(0).map(|(k, _)| k.to_vec()),
+            Some(b"group/admins/size".to_vec())
+        );
+        assert_eq!(tree.rank(b"user/"), 1);
+        assert_eq!(tree.rank(b"user/carol/age"), 2);
+        assert_eq!(tree.rank(b"z"), 3);
+        let snapshot = tree.clone();
+        tree.insert(b"user/dave/age", 60);
+        assert_eq!(snapshot.summary_prefix(b"user/"), (2, 90));
+        assert_eq!(tree.summary_prefix(b"user/"), (3, 150));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[quickcheck]
+    fn par_combine(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        let (at, bt) = (map_tree(&a), map_tree(&b));
+        let outer = |a: &u8, b: &u8| Some(a.wrapping_add(*b)).filter(|x| x % 3 != 0);
+        let inner = |a: &u8, b: &u8| Some(a.wrapping_mul(*b)).filter(|x| x % 3 != 0);
+        let left = |a: &u8, b: Option<&u8>| match b {
+            Some(b) if a > b => None,
+            _ => Some(*a),
+        };
+        let mut res = at.par_union(&bt) == at.union(&bt)
+            && at.par_intersection(&bt) == at.intersection(&bt)
+            && at.par_difference(&bt) == at.difference(&bt)
+            && at.par_outer_combine(&bt, outer) == at.outer_combine(&bt, outer)
+            && at.par_inner_combine(&bt, inner) == at.inner_combine(&bt, inner)
+            && at.par_left_combine(&bt, left) == at.left_combine(&bt, left);
+        // with a small budget, to also exercise the switch to the sequential path
+        for budget in 2..6 {
+            res &= rayon_support::par_outer_combine(&at, &bt, outer, budget)
+                == at.outer_combine(&bt, outer)
+                && rayon_support::par_inner_combine(&at, &bt, inner, budget)
+                    == at.inner_combine(&bt, inner)
+                && rayon_support::par_left_combine(&at, &bt, left, budget)
+                    == at.left_combine(&bt, left);
+        }
+        let (aa, ba) = (arc_tree(&a), arc_tree(&b));
+        res && aa.par_union(&ba) == aa.union(&ba)
+            && aa.par_intersection(&ba) == aa.intersection(&ba)
+            && aa.par_difference(&ba) == aa.difference(&ba)
+            && aa.par_difference(&bt) == aa.difference(&bt)
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_combine_large() {
+        let a: RadixTree<u8, u32> = (0..100000u32).map(|i| (i.to_string(), i)).collect();
+        let b: RadixTree<u8, u32> = (50000..150000u32).map(|i| (i.to_string(), i)).collect();
+        assert_eq!(a.par_union(&b), a.union(&b));
+        assert_eq!(a.par_intersection(&b), a.intersection(&b));
+        assert_eq!(a.par_difference(&b), a.difference(&b));
+        assert_eq!(a.par_intersection(&b).iter().count(), 50000);
+        let a = ArcRadixTree::from(a);
+        let b = ArcRadixTree::from(b);
+        assert_eq!(a.par_union(&b), a.union(&b));
+        assert_eq!(a.par_difference(&b).iter().count(), 50000);
+    }
+
+    fn hash<T: Hash>(value: &T) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[quickcheck]
+    fn std_traits(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        let (ta, tb) = (map_tree(&a), map_tree(&b));
+        let (aa, ab) = (arc_tree(&a), arc_tree(&b));
+        (ta == tb) == (a == b)
+            && (aa == ab) == (a == b)
+            && ta.cmp(&tb) == a.cmp(&b)
+            && aa.cmp(&ab) == a.cmp(&b)
+            && ta.partial_cmp(&tb) == a.partial_cmp(&b)
+            && hash(&ta) == hash(&aa)
+            && (a != b || hash(&ta) == hash(&tb))
+            && (&ta).into_iter().count() == a.len()
+            && (&aa).into_iter().count() == a.len()
+    }
+
+    #[quickcheck]
+    fn extend(a: BTreeMap<Vec<u8>, u8>, b: Vec<(Vec<u8>, u8)>) -> bool {
+        let mut expected = a.clone();
+        expected.extend(b.clone());
+        let mut flat = map_tree(&a);
+        flat.extend(b.clone());
+        let mut arc = arc_tree(&a);
+        arc.extend(b);
+        flat == map_tree(&expected) && arc == arc_tree(&expected)
+    }
+
+    #[test]
+    fn std_traits_sample() {
+        let empty = RadixTree::<u8, u8>::empty();
+        assert_eq!(
+            format!("{:?}", empty),
+            "RadixTree { prefix: [], value: None, children: [] }"
+        );
+        assert_eq!(
+            format!("{:?}", ArcRadixTree::<u8, u8>::empty()),
+            "ArcRadixTree { prefix: [], value: None, children: [] }"
+        );
+        let a = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let snapshot = arc_tree(&a);
+        let mut tree = snapshot.clone();
+        assert!(tree == snapshot);
+        tree.insert(b"123", 0);
+        assert!(tree != snapshot);
+        assert!(tree < snapshot);
+        let mut keys = Vec::new();
+        for (k, _) in &tree {
+            keys.push(k);
+        }
+        assert_eq!(keys.len(), 1000);
+    }
+
+    #[cfg(feature = "rkyv")]
+    #[test]
+    fn archived_arc_std_traits() {
+        use rkyv::ser::{serializers::AllocSerializer, Serializer};
+        let a = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let tree = arc_tree(&a);
+        let mut serializer = AllocSerializer::<256>::default();
+        serializer.serialize_value(&tree).unwrap();
+        let bytes = serializer.into_serializer().into_inner();
+        let archived = unsafe { rkyv::archived_root::<ArcRadixTree<u8, u8>>(&bytes) };
+        assert!(tree == *archived);
+        assert!(*archived == tree);
+        assert_eq!(hash(&tree), hash(archived));
+        assert_eq!(archived.cmp(archived), Ordering::Equal);
+        let mut tree1 = tree.clone();
+        tree1.insert(b"x", 0);
+        assert!(*archived != tree1);
+    }
+
+    #[quickcheck]
+    fn minimize(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        // use a small alphabet and few values, so there are identical subtrees
+        let small = |m: BTreeMap<Vec<u8>, u8>| {
+            m.into_iter()
+                .map(|(k, v)| (k.iter().map(|x| x % 3).collect::<Vec<_>>(), v % 2))
+                .collect::<BTreeMap<_, _>>()
+        };
+        let (a, b) = (small(a), small(b));
+        let tree = arc_tree(&a);
+        let mut minimized = tree.clone();
+        minimized.minimize();
+        let mut res = minimized == tree;
+        // minimizing again does not change anything
+        let mut arcs = BTreeMap::new();
+        minimized.all_arcs(&mut arcs);
+        let mut again = minimized.clone();
+        again.minimize();
+        let mut arcs1 = BTreeMap::new();
+        again.all_arcs(&mut arcs1);
+        res &= arcs.keys().eq(arcs1.keys());
+        // modifications of a minimized tree only affect the modified keys
+        let mut expected = a;
+        for (k, v) in b {
+            minimized.insert(&k, v);
+            expected.insert(k, v);
+        }
+        res && minimized
+            .iter()
+            .map(|(k, v)| (k.to_vec(), *v))
+            .eq(expected.into_iter())
+    }
+
+    #[test]
+    fn minimize_sample() {
+        let words = ["walk", "talk", "balk", "chalk", "stalk"]
+            .iter()
+            .flat_map(|w| {
+                ["", "s", "ed", "ing"]
+                    .iter()
+                    .map(move |s| format!("{}{}", w, s))
+            })
+            .collect::<Vec<_>>();
+        let tree: ArcRadixTree<u8, ()> = words.iter().map(|w| (w, ())).collect();
+        let mut minimized = tree.clone();
+        minimized.minimize();
+        assert_eq!(minimized, tree);
+        let count = |tree: &ArcRadixTree<u8, ()>| {
+            let mut arcs = BTreeMap::new();
+            tree.all_arcs(&mut arcs);
+            arcs.len()
+        };
+        // the children lists [ed, ing, s] of the five stems are shared
+        assert!(count(&minimized) < count(&tree));
+        let shared = minimized
+            .children()
+            .iter()
+            .filter(|c| c.children().len() == 3)
+            .map(|c| c.children().as_ptr())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(shared.len(), 1);
+        // copy on write still works for shared lists
+        minimized.insert(b"walker", ());
+        assert!(minimized.contains_key(b"walker"));
+        assert!(!minimized.contains_key(b"talker"));
+        assert_eq!(minimized.iter().count(), words.len() + 1);
+    }
+
+    #[cfg(feature = "rkyv")]
+    #[test]
+    fn minimize_archive() {
+        use rkyv::ser::{serializers::AllocSerializer, Serializer};
+        let serialize = |tree: &ArcRadixTree<u8, u8>| {
+            let mut serializer = AllocSerializer::<256>::default();
+            serializer.serialize_value(tree).unwrap();
+            serializer.into_serializer().into_inner()
+        };
+        // numbers in decimal have lots of identical subtrees
+        let tree: ArcRadixTree<u8, u8> = (0..10000u32).map(|i| (i.to_string(), 0)).collect();
+        let mut minimized = tree.clone();
+        minimized.minimize();
+        let bytes = serialize(&tree);
+        let minimized_bytes = serialize(&minimized);
+        assert!(minimized_bytes.len() * 10 < bytes.len());
+        let archived = unsafe { rkyv::archived_root::<ArcRadixTree<u8, u8>>(&minimized_bytes) };
+        assert!(*archived == tree);
+    }
+
+    #[cfg(feature = "merkle")]
+    #[quickcheck]
+    fn merkle(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        let (ta, tb) = (arc_tree(&a), arc_tree(&b));
+        // cached hash is the same as the hash computed from scratch for another flavour
+        let mut res = *ta.merkle_hash() == merkle_hash(&map_tree(&a));
+        res &= (ta.merkle_hash() == tb.merkle_hash()) == (a == b);
+        // after modifications, only valid hashes remain cached
+        let mut modified = ta.clone();
+        let mut expected = a;
+        for (k, v) in b {
+            modified.insert(&k, v);
+            expected.insert(k, v);
+        }
+        modified.remove(&[]);
+        expected.remove(&vec![]);
+        res && modified.merkle_hash() == arc_tree(&expected).merkle_hash()
+    }
+
+    #[cfg(feature = "merkle")]
+    #[quickcheck]
+    fn diff_prefixes(a: BTreeMap<Vec<u8>, u8>, b: BTreeMap<Vec<u8>, u8>) -> bool {
+        let prefixes = arc_tree(&a).diff_prefixes(&arc_tree(&b));
+        let mut res = prefixes.windows(2).all(|w| w[0] < w[1]);
+        // every differing key is covered
+        for change in map_diff(&a, &b) {
+            res &= prefixes.iter().any(|p| change.key().starts_with(p));
+        }
+        // every prefix is a differing key, or a subtree that is only in one of the trees
+        for p in &prefixes {
+            let in_a = a.keys().any(|k| k.starts_with(p));
+            let in_b = b.keys().any(|k| k.starts_with(p));
+            res &= a.get(p) != b.get(p) || (in_a != in_b);
+        }
+        res
+    }
+
+    #[cfg(feature = "merkle")]
+    #[test]
+    fn diff_prefixes_sample() {
+        let a = (0..1000u32)
+            .map(|i| (i.to_string().into_bytes(), (i % 256) as u8))
+            .collect::<BTreeMap<_, _>>();
+        let t0 = arc_tree(&a);
+        // a replica that shares no structure
+        let mut t1 = arc_tree(&a);
+        assert_eq!(t0.merkle_hash(), t1.merkle_hash());
+        assert!(t0.diff_prefixes(&t1).is_empty());
+        t1.insert(b"123", 0);
+        t1.insert(b"12345", 1);
+        t1.remove(b"999");
+        t1.insert(b"x", 0);
+        assert_ne!(t0.merkle_hash(), t1.merkle_hash());
+        assert_eq!(
+            t0.diff_prefixes(&t1),
+            vec![
+                b"123".to_vec(),
+                b"12345".to_vec(),
+                b"999".to_vec(),
+                b"x".to_vec()
+            ]
+        );
+    }
+
+    #[cfg(feature = "merkle")]
+    #[test]
+    fn merkle_stable() {
+        let tree: ArcRadixTree<u8, u8> = [("a", 1), ("ab", 2), ("b", 3)].iter().cloned().collect();
+        let hex = tree
+            .merkle_hash()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        // persisted hashes must never change
+        assert_eq!(
+            hex,
+            "d9144d4f2e02bf59cd0ab0f73fe7b0a3ddafb9e6902085b8b10933482e27231f"
+        );
+    }
+
+    #[cfg(feature = "lazy_radixtree")]
+    #[test]
+    fn lazy_std_traits() {
+        let keys = (0..1000u32).map(|i| i.to_string()).collect::<BTreeSet<_>>();
+        let a: LazyRadixTree<u8, ()> = keys.iter().map(|k| (k, ())).collect();
+        let mut b: LazyRadixTree<u8, ()> = LazyRadixTree::default();
+        b.extend(keys.iter().rev().map(|k| (k, ())));
+        assert!(a == b);
+        assert_eq!(hash(&a), hash(&b));
+        assert!(format!("{:?}", a).starts_with("LazyRadixTree {"));
+    }
+
+    #[test]
+    fn non_archivable_values() {
+        // a value type that does not implement rkyv::Archive
+        #[derive(Debug, Clone, PartialEq)]
+        struct Value(Arc<str>);
+        let value = |text: &str| Value(text.into());
+        let a: RadixTree<u8, Value> = vec![("a", value("1")), ("ab", value("2"))]
+            .into_iter()
+            .collect();
+        let b: ArcRadixTree<u8, Value> = vec![("ab", value("3")), ("b", value("4"))]
+            .into_iter()
+            .collect();
+        let joined: RadixTree<u8, Value> = a.outer_join(&b, |arg| {
+            Some(match arg {
+                OuterJoinArg::Left(_, v) | OuterJoinArg::Right(_, v) => v.clone(),
+                OuterJoinArg::Both(_, v, w) => value(&format!("{}{}", v.0, w.0)),
+            })
+        });
+        let pairs = joined
+            .iter()
+            .map(|(k, v)| (k.to_vec(), v.0.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pairs,
+            vec![
+                (b"a".to_vec(), "1".to_string()),
+                (b"ab".to_vec(), "23".to_string()),
+                (b"b".to_vec(), "4".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_arc() {
+        let mut a: ArcRadixTree<u8, ()> = test_tree(&["a", "ab", "abc", "b"]).into();
+        let snapshot = a.clone();
+        let mut arcs = std::collections::BTreeMap::new();
+        snapshot.all_arcs(&mut arcs);
+        // removing something that is not there must not copy anything
+        assert_eq!(a.remove(b"abx"), None);
+        a.remove_prefix(b"c");
+        let mut arcs1 = std::collections::BTreeMap::new();
+        a.all_arcs(&mut arcs1);
+        assert_eq!(
+            arcs.keys().collect::<Vec<_>>(),
+            arcs1.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(a.remove(b"ab"), Some(()));
+        a.remove_prefix(b"b");
+        let keys = a.iter().map(|(k, _)| k.to_vec()).collect::<Vec<_>>();
+        assert_eq!(keys, vec![b"a".to_vec(), b"abc".to_vec()]);
+        // the snapshot is unchanged
+        assert_eq!(snapshot.iter().count(), 4);
+    }
+
     #[test]
     fn retain_prefix_sample1() {
         let a = r2t(&btreeset! { vec![0]});
@@ -1886,38 +4491,3 @@ mod test {
         assert_eq!(test, expected);
     }
 }
-
-fn offset_from<T, U>(base: *const T, p: *const U) -> usize {
-    let base = base as usize;
-    let p = p as usize;
-    assert!(p >= base);
-    p - base
-}
-
-fn location<T>(x: &T) -> usize {
-    (x as *const T) as usize
-}
-
-/// Helper to contain an object and an interator that takes the object by reference
-///
-/// This is a quick way to implement into_iter in terms of iter.
-pub struct ObjAndIter<K, V> {
-    k: Box<K>,
-    v: V,
-}
-
-impl<K: 'static, V> ObjAndIter<K, V> {
-    fn new(k: Box<K>, f: impl Fn(&'static K) -> V) -> Self {
-        let kr = unsafe { std::mem::transmute(k.as_ref()) };
-        let v = f(kr);
-        Self { k, v }
-    }
-}
-
-impl<K: 'static, V: Iterator> Iterator for ObjAndIter<K, V> {
-    type Item = V::Item;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.v.next()
-    }
-}
@@ -0,0 +1,279 @@
+//! Glob style patterns over radix tree keys
+use super::TKey;
+
+/// A single element of a [Pattern]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PatternElement<K> {
+    /// matches exactly the given key element
+    Exact(K),
+    /// matches any single key element, `?` in a glob
+    Any,
+    /// matches any run of key elements, including the empty run, `*` in a glob
+    AnyRun,
+    /// matches a single key element that is in one of the inclusive ranges, or in none of them if negated.
+    /// `[a-z_]` or `[!0-9]` in a glob
+    Class { ranges: Vec<(K, K)>, negated: bool },
+}
+
+impl<K: TKey> PatternElement<K> {
+    /// true if this element consumes the given key element
+    fn matches(&self, k: &K) -> bool {
+        match self {
+            Self::Exact(x) => x == k,
+            Self::Any | Self::AnyRun => true,
+            Self::Class { ranges, negated } => {
+                ranges.iter().any(|(lo, hi)| lo <= k && k <= hi) != *negated
+            }
+        }
+    }
+}
+
+/// A pattern that matches entire keys, to be used with [matches](super::AbstractRadixTree::matches)
+///
+/// The pattern is matched by simulating a nondeterministic automaton whose states are the positions in the
+/// pattern, so matching is linear in the key length even with many `*`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pattern<K>(Vec<PatternElement<K>>);
+
+impl<K: TKey> Pattern<K> {
+    /// Creates a pattern from a sequence of elements
+    pub fn new(elements: impl IntoIterator<Item = PatternElement<K>>) -> Self {
+        let mut res: Vec<PatternElement<K>> = Vec::new();
+        for element in elements {
+            // consecutive runs are equivalent to a single run
+            if element == PatternElement::AnyRun && res.last() == Some(&PatternElement::AnyRun) {
+                continue;
+            }
+            res.push(element);
+        }
+        Self(res)
+    }
+
+    /// The elements of the pattern
+    pub fn elements(&self) -> &[PatternElement<K>] {
+        &self.0
+    }
+
+    /// True if the entire key matches the pattern
+    pub fn is_match(&self, key: &[K]) -> bool {
+        let w = self.width();
+        let mut states = Vec::with_capacity(w * 2);
+        self.start(&mut states);
+        for k in key {
+            if !self.step(&mut states, k) {
+                return false;
+            }
+            states.drain(..w);
+        }
+        self.is_accepting(&states)
+    }
+
+    /// number of states
+    pub(crate) fn width(&self) -> usize {
+        self.0.len() + 1
+    }
+
+    /// append the initial states
+    pub(crate) fn start(&self, states: &mut Vec<bool>) {
+        let start = states.len();
+        states.resize(start + self.width(), false);
+        states[start] = true;
+        self.close(&mut states[start..]);
+    }
+
+    /// append the states after consuming `k`, given the last states. Returns false if there are none.
+    pub(crate) fn step(&self, states: &mut Vec<bool>, k: &K) -> bool {
+        let w = self.width();
+        let prev = states.len() - w;
+        states.resize(prev + 2 * w, false);
+        let (prev, next) = states[prev..].split_at_mut(w);
+        let mut any = false;
+        for (i, element) in self.0.iter().enumerate() {
+            if prev[i] && element.matches(k) {
+                // a run stays in its state, everything else moves to the next one
+                let target = if *element == PatternElement::AnyRun {
+                    i
+                } else {
+                    i + 1
+                };
+                next[target] = true;
+                any = true;
+            }
+        }
+        self.close(next);
+        any
+    }
+
+    /// true if the last states contain the final state
+    pub(crate) fn is_accepting(&self, states: &[bool]) -> bool {
+        states.last() == Some(&true)
+    }
+
+    /// a run can also match nothing, so being in front of it means also being behind it
+    fn close(&self, states: &mut [bool]) {
+        for (i, element) in self.0.iter().enumerate() {
+            if states[i] && *element == PatternElement::AnyRun {
+                states[i + 1] = true;
+            }
+        }
+    }
+}
+
+impl<K: TKey + From<u8>> Pattern<K> {
+    /// Parses a glob, e.g. `b"user/*/settings"`
+    ///
+    /// - `?` matches any single element
+    /// - `*` matches any run of elements
+    /// - `[abc]`, `[a-z]` and `[!a-z]` match a single element from a class, or not from a class.
+    ///   a `]` directly after the opening bracket is part of the class.
+    /// - `\` makes the next element match literally
+    ///
+    /// Brackets that are not closed and a trailing `\` are taken literally.
+    pub fn glob(text: &[K]) -> Self {
+        let special = |c: u8| K::from(c);
+        let mut res = Vec::new();
+        let mut i = 0;
+        while i < text.len() {
+            let c = text[i];
+            i += 1;
+            res.push(if c == special(b'?') {
+                PatternElement::Any
+            } else if c == special(b'*') {
+                PatternElement::AnyRun
+            } else if c == special(b'\\') && i < text.len() {
+                i += 1;
+                PatternElement::Exact(text[i - 1])
+            } else if c == special(b'[') {
+                match Self::class(&text[i..]) {
+                    Some((element, n)) => {
+                        i += n;
+                        element
+                    }
+                    None => PatternElement::Exact(c),
+                }
+            } else {
+                PatternElement::Exact(c)
+            });
+        }
+        Self::new(res)
+    }
+
+    /// parse a class after the opening bracket, returning it and the number of elements consumed
+    fn class(text: &[K]) -> Option<(PatternElement<K>, usize)> {
+        let special = |c: u8| K::from(c);
+        let mut i = 0;
+        let negated = text
+            .first()
+            .map(|c| *c == special(b'!') || *c == special(b'^'))
+            .unwrap_or_default();
+        if negated {
+            i += 1;
+        }
+        let mut ranges = Vec::new();
+        let start = i;
+        while i < text.len() {
+            let lo = text[i];
+            if lo == special(b']') && i > start {
+                return Some((PatternElement::Class { ranges, negated }, i + 1));
+            }
+            if i + 2 < text.len() && text[i + 1] == special(b'-') && text[i + 2] != special(b']') {
+                ranges.push((lo, text[i + 2]));
+                i += 3;
+            } else {
+                ranges.push((lo, lo));
+                i += 1;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck_macros::quickcheck;
+
+    fn glob(text: &str) -> Pattern<u8> {
+        Pattern::glob(text.as_bytes())
+    }
+
+    #[test]
+    fn glob_parse() {
+        use PatternElement::*;
+        assert_eq!(glob("a?*").elements(), &[Exact(b'a'), Any, AnyRun]);
+        assert_eq!(glob("a**b").elements(), &[Exact(b'a'), AnyRun, Exact(b'b')]);
+        assert_eq!(glob("\\*\\").elements(), &[Exact(b'*'), Exact(b'\\')]);
+        assert_eq!(
+            glob("[a-cx][!]]").elements(),
+            &[
+                Class {
+                    ranges: vec![(b'a', b'c'), (b'x', b'x')],
+                    negated: false
+                },
+                Class {
+                    ranges: vec![(b']', b']')],
+                    negated: true
+                }
+            ]
+        );
+        assert_eq!(
+            glob("[a-]").elements(),
+            &[Class {
+                ranges: vec![(b'a', b'a'), (b'-', b'-')],
+                negated: false
+            }]
+        );
+        assert_eq!(
+            glob("[ab").elements(),
+            &[Exact(b'['), Exact(b'a'), Exact(b'b')]
+        );
+    }
+
+    #[test]
+    fn glob_match() {
+        assert!(glob("user/*/settings").is_match(b"user/bob/settings"));
+        assert!(glob("user/*/settings").is_match(b"user//settings"));
+        assert!(glob("user/*/settings").is_match(b"user/a/b/settings"));
+        assert!(!glob("user/*/settings").is_match(b"user/bob/settings2"));
+        assert!(glob("?").is_match(b"x"));
+        assert!(!glob("?").is_match(b""));
+        assert!(glob("*").is_match(b""));
+        assert!(glob("").is_match(b""));
+        assert!(!glob("").is_match(b"a"));
+        assert!(glob("[a-c]*[!0-9]").is_match(b"b12x"));
+        assert!(!glob("[a-c]*[!0-9]").is_match(b"b123"));
+        assert!(!glob("[a-c]*").is_match(b"d"));
+        assert!(glob("*a*a*").is_match(b"banana"));
+        assert!(!glob("*a*a*a*a*").is_match(b"banana"));
+    }
+
+    /// reference implementation by backtracking
+    fn is_match_slow(pattern: &[PatternElement<u8>], key: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => key.is_empty(),
+            Some((PatternElement::AnyRun, rest)) => {
+                (0..=key.len()).any(|i| is_match_slow(rest, &key[i..]))
+            }
+            Some((element, rest)) => match key.split_first() {
+                Some((k, key)) => element.matches(k) && is_match_slow(rest, key),
+                None => false,
+            },
+        }
+    }
+
+    #[quickcheck]
+    fn glob_match_slow(pattern: Vec<u8>, key: Vec<u8>) -> bool {
+        // small alphabet including the special characters, so there are matches
+        let alphabet = b"ab?*[]!-";
+        let pattern = pattern
+            .iter()
+            .map(|x| alphabet[*x as usize % alphabet.len()])
+            .collect::<Vec<_>>();
+        let key = key
+            .iter()
+            .map(|x| b"ab"[*x as usize % 2])
+            .collect::<Vec<_>>();
+        let pattern = Pattern::glob(&pattern);
+        pattern.is_match(&key) == is_match_slow(pattern.elements(), &key)
+    }
+}
@@ -0,0 +1,246 @@
+//! Parallel versions of the combine operations
+//!
+//! These follow the sequential implementations in the parent module node by node, but combine the children
+//! of a node in parallel. Work is split until there are enough tasks to keep all threads busy, and below
+//! that the sequential implementations are used, so small trees are not slowed down by the splitting.
+use super::{
+    common_prefix, inner_combine, left_combine, materialize, outer_combine, AbstractRadixTree,
+    AbstractRadixTreeMut, TKey, TValue,
+};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+
+/// number of tasks per thread to aim for, so uneven subtrees can be balanced by work stealing
+const TASKS_PER_THREAD: usize = 16;
+
+/// the initial budget of tasks
+pub(crate) fn budget() -> usize {
+    rayon::current_num_threads() * TASKS_PER_THREAD
+}
+
+/// a child that exists on the left, right or both sides
+enum Pair<'a, A, B> {
+    Left(&'a A),
+    Right(&'a B),
+    Both(&'a A, &'a B),
+}
+
+/// Pair up the children of two nodes by their first element, and map over the pairs in parallel.
+///
+/// Each pair gets an even share of the budget. Empty results are removed by `unsplit` afterwards.
+fn par_children<K, V, W, A, B, R, F>(a: &[A], b: &[B], budget: usize, f: F) -> Vec<R>
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    A: AbstractRadixTree<K, V> + Sync,
+    B: AbstractRadixTree<K, W> + Sync,
+    R: Send,
+    F: Fn(Pair<A, B>, usize) -> Option<R> + Sync,
+{
+    let mut pairs = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    loop {
+        let ordering = match (a.get(i), b.get(j)) {
+            (Some(a), Some(b)) => a.prefix()[0].cmp(&b.prefix()[0]),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+        pairs.push(match ordering {
+            Ordering::Less => Pair::Left(&a[i]),
+            Ordering::Greater => Pair::Right(&b[j]),
+            Ordering::Equal => Pair::Both(&a[i], &b[j]),
+        });
+        if ordering != Ordering::Greater {
+            i += 1;
+        }
+        if ordering != Ordering::Less {
+            j += 1;
+        }
+    }
+    let budget = budget / pairs.len().max(1);
+    pairs
+        .into_par_iter()
+        .filter_map(|pair| f(pair, budget))
+        .collect()
+}
+
+/// Parallel outer combine, same result as [outer_combine]
+pub(crate) fn par_outer_combine<K, V, R, A, B, F>(a: &A, b: &B, f: F, budget: usize) -> R
+where
+    K: TKey,
+    V: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R> + Send + Sync,
+    A: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    B: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    F: Fn(&V, &V) -> Option<V> + Copy + Send + Sync,
+{
+    if budget <= 1 {
+        return outer_combine(a, b, f);
+    }
+    let n = common_prefix(a.prefix(), b.prefix());
+    let prefix = a.prefix()[..n].into();
+    let (value, children) = if n == a.prefix().len() && n == b.prefix().len() {
+        // prefixes are identical
+        let value = match (a.value(), b.value()) {
+            (Some(a), Some(b)) => f(a, b),
+            (Some(a), None) => Some(a.clone()),
+            (None, Some(b)) => Some(b.clone()),
+            (None, None) => None,
+        };
+        (value, outer_children(a.children(), b.children(), f, budget))
+    } else if n == a.prefix().len() {
+        // a is a prefix of b
+        let b = b.materialize_shortened(n);
+        let value = a.value().cloned();
+        (value, outer_children(a.children(), &[b], f, budget))
+    } else if n == b.prefix().len() {
+        // b is a prefix of a
+        let a = a.materialize_shortened(n);
+        let value = b.value().cloned();
+        (value, outer_children(&[a], b.children(), f, budget))
+    } else {
+        // disjoint, nothing to combine
+        return outer_combine(a, b, f);
+    };
+    let mut res = R::new(prefix, value, children);
+    res.unsplit();
+    res
+}
+
+fn outer_children<K, V, R, A, B, F>(a: &[A], b: &[B], f: F, budget: usize) -> Vec<R>
+where
+    K: TKey,
+    V: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R> + Send + Sync,
+    A: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    B: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    F: Fn(&V, &V) -> Option<V> + Copy + Send + Sync,
+{
+    par_children(a, b, budget, |pair, budget| {
+        Some(match pair {
+            Pair::Left(a) => materialize(a),
+            Pair::Right(b) => materialize(b),
+            Pair::Both(a, b) => par_outer_combine(a, b, f, budget),
+        })
+    })
+}
+
+/// Parallel inner combine, same result as [inner_combine]
+pub(crate) fn par_inner_combine<K, V, W, R, A, B, F>(a: &A, b: &B, f: F, budget: usize) -> R
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R> + Send + Sync,
+    A: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    B: AbstractRadixTree<K, W> + Sync,
+    B::Materialized: Sync,
+    F: Fn(&V, &W) -> Option<V> + Copy + Send + Sync,
+{
+    if budget <= 1 {
+        return inner_combine(a, b, f);
+    }
+    let n = common_prefix(a.prefix(), b.prefix());
+    let prefix = a.prefix()[..n].into();
+    let (value, children) = if n == a.prefix().len() && n == b.prefix().len() {
+        // prefixes are identical
+        let value = match (a.value(), b.value()) {
+            (Some(a), Some(b)) => f(a, b),
+            _ => None,
+        };
+        (value, inner_children(a.children(), b.children(), f, budget))
+    } else if n == a.prefix().len() {
+        // a is a prefix of b
+        let b = b.materialize_shortened(n);
+        (None, inner_children(a.children(), &[b], f, budget))
+    } else if n == b.prefix().len() {
+        // b is a prefix of a
+        let a = a.materialize_shortened(n);
+        (None, inner_children(&[a], b.children(), f, budget))
+    } else {
+        // disjoint
+        return R::default();
+    };
+    let mut res = R::new(prefix, value, children);
+    res.unsplit();
+    res
+}
+
+fn inner_children<K, V, W, R, A, B, F>(a: &[A], b: &[B], f: F, budget: usize) -> Vec<R>
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R> + Send + Sync,
+    A: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    B: AbstractRadixTree<K, W> + Sync,
+    B::Materialized: Sync,
+    F: Fn(&V, &W) -> Option<V> + Copy + Send + Sync,
+{
+    par_children(a, b, budget, |pair, budget| match pair {
+        Pair::Both(a, b) => Some(par_inner_combine(a, b, f, budget)),
+        _ => None,
+    })
+}
+
+/// Parallel left combine, same result as [left_combine]
+pub(crate) fn par_left_combine<K, V, W, R, A, B, F>(a: &A, b: &B, f: F, budget: usize) -> R
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R> + Send + Sync,
+    A: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    B: AbstractRadixTree<K, W> + Sync,
+    B::Materialized: Sync,
+    F: Fn(&V, Option<&W>) -> Option<V> + Copy + Send + Sync,
+{
+    if budget <= 1 {
+        return left_combine(a, b, f);
+    }
+    let n = common_prefix(a.prefix(), b.prefix());
+    let prefix = a.prefix()[..n].into();
+    let (value, children) = if n == a.prefix().len() && n == b.prefix().len() {
+        // prefixes are identical
+        let value = match (a.value(), b.value()) {
+            (Some(a), b) => f(a, b),
+            _ => None,
+        };
+        (value, left_children(a.children(), b.children(), f, budget))
+    } else if n == a.prefix().len() {
+        // a is a prefix of b
+        let b = b.materialize_shortened(n);
+        let value = a.value().cloned();
+        (value, left_children(a.children(), &[b], f, budget))
+    } else if n == b.prefix().len() {
+        // b is a prefix of a
+        let a = a.materialize_shortened(n);
+        (None, left_children(&[a], b.children(), f, budget))
+    } else {
+        // disjoint, nothing to combine
+        return left_combine(a, b, f);
+    };
+    let mut res = R::new(prefix, value, children);
+    res.unsplit();
+    res
+}
+
+fn left_children<K, V, W, R, A, B, F>(a: &[A], b: &[B], f: F, budget: usize) -> Vec<R>
+where
+    K: TKey,
+    V: TValue,
+    W: TValue,
+    R: AbstractRadixTreeMut<K, V, Materialized = R> + Send + Sync,
+    A: AbstractRadixTree<K, V, Materialized = R> + Sync,
+    B: AbstractRadixTree<K, W> + Sync,
+    B::Materialized: Sync,
+    F: Fn(&V, Option<&W>) -> Option<V> + Copy + Send + Sync,
+{
+    par_children(a, b, budget, |pair, budget| match pair {
+        Pair::Left(a) => Some(materialize(a)),
+        Pair::Right(_) => None,
+        Pair::Both(a, b) => Some(par_left_combine(a, b, f, budget)),
+    })
+}
@@ -0,0 +1,439 @@
+//! serde support for radix trees
+//!
+//! The [Serialize] and [Deserialize] impls of the radix trees use a flat sequence of `(key, value)` pairs,
+//! sorted by key. A sequence of pairs is used instead of a map, since many formats, like json, only support
+//! string map keys.
+//!
+//! In addition there are two structural forms, to be used with `#[serde(with = "...")]`:
+//! - [structural] serializes the nodes of the tree as nested `(prefix, value, children)` tuples.
+//! - [shared] serializes a number of [ArcRadixTree]s as a DAG, so shared subtrees are only written once.
+use super::{AbstractRadixTree, AbstractRadixTreeMut, TKey, TValue};
+use crate::dedup::Keep;
+use core::{fmt, marker::PhantomData};
+use serde::{
+    de::{self, Deserialize, Deserializer, SeqAccess, Visitor},
+    ser::{Serialize, SerializeSeq, SerializeTuple, Serializer},
+};
+
+/// A slice, serialized as a sequence
+struct Seq<'a, T>(&'a [T]);
+
+impl<'a, T: Serialize> Serialize for Seq<'a, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0)
+    }
+}
+
+/// A sequence, deserialized into a vec
+struct SeqVec<T>(Vec<T>);
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for SeqVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SeqVecVisitor(PhantomData))
+    }
+}
+
+struct SeqVecVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> Visitor<'de> for SeqVecVisitor<T> {
+    type Value = SeqVec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        // do not trust the size hint too much
+        let mut res = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(elem) = seq.next_element()? {
+            res.push(elem);
+        }
+        Ok(SeqVec(res))
+    }
+}
+
+/// get the next element of a tuple, or fail with an invalid length error
+fn next<'de, A: SeqAccess<'de>, T: Deserialize<'de>>(
+    seq: &mut A,
+    index: usize,
+    visitor: &impl Visitor<'de>,
+) -> Result<T, A::Error> {
+    seq.next_element()?
+        .ok_or_else(|| de::Error::invalid_length(index, visitor))
+}
+
+/// Check that a node built from untrusted data is in canonical form.
+///
+/// The children have already been checked, so it is sufficient to check the direct children.
+fn check_node<K: TKey, V: TValue, T: AbstractRadixTree<K, V>>(
+    node: &T,
+    root: bool,
+) -> Result<(), &'static str> {
+    let children = node.children();
+    for child in children {
+        if child.prefix().is_empty() {
+            return Err("child with empty prefix");
+        }
+    }
+    for pair in children.windows(2) {
+        if pair[0].prefix()[0] >= pair[1].prefix()[0] {
+            return Err("children not sorted");
+        }
+    }
+    if node.value().is_none() {
+        match children.len() {
+            0 if !root => return Err("empty child"),
+            0 if !node.prefix().is_empty() => return Err("empty tree with prefix"),
+            1 => return Err("node without value with a single child"),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn serialize_flat<K, V, T, S>(tree: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    K: TKey + Serialize,
+    V: TValue + Serialize,
+    T: AbstractRadixTree<K, V>,
+    S: Serializer,
+{
+    let mut state = serializer.serialize_seq(Some(tree.iter().count()))?;
+    for (k, v) in tree.iter() {
+        state.serialize_element(&(Seq(&k), v))?;
+    }
+    state.end()
+}
+
+fn deserialize_flat<'de, K, V, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    K: TKey + Deserialize<'de>,
+    V: TValue + Deserialize<'de>,
+    T: AbstractRadixTreeMut<K, V>,
+    D: Deserializer<'de>,
+{
+    let SeqVec(pairs) = SeqVec::<(SeqVec<K>, V)>::deserialize(deserializer)?;
+    // the pairs are not trusted to be sorted or unique
+    Ok(T::from_unsorted_iter(
+        pairs.into_iter().map(|(SeqVec(k), v)| (k, v)),
+        Keep::Last,
+    ))
+}
+
+/// Serialize and deserialize a radix tree as nested `(prefix, value, children)` tuples.
+///
+/// Use this with `#[serde(with = "vec_collections::radix_tree::structural")]`. It works for all flavours
+/// of radix tree. Deserialization fails if the nodes are not in canonical form.
+pub mod structural {
+    use super::*;
+
+    struct Node<'a, K, V, T>(&'a T, PhantomData<(K, V)>);
+
+    impl<'a, K, V, T> Serialize for Node<'a, K, V, T>
+    where
+        K: TKey + Serialize,
+        V: TValue + Serialize,
+        T: AbstractRadixTree<K, V>,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut state = serializer.serialize_tuple(3)?;
+            state.serialize_element(&Seq(self.0.prefix()))?;
+            state.serialize_element(&self.0.value())?;
+            state.serialize_element(&Children(self.0.children(), PhantomData))?;
+            state.end()
+        }
+    }
+
+    struct Children<'a, K, V, T>(&'a [T], PhantomData<(K, V)>);
+
+    impl<'a, K, V, T> Serialize for Children<'a, K, V, T>
+    where
+        K: TKey + Serialize,
+        V: TValue + Serialize,
+        T: AbstractRadixTree<K, V>,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().map(|child| Node(child, PhantomData)))
+        }
+    }
+
+    struct NodeDe<K, V, T>(T, PhantomData<(K, V)>);
+
+    impl<'de, K, V, T> Deserialize<'de> for NodeDe<K, V, T>
+    where
+        K: TKey + Deserialize<'de>,
+        V: TValue + Deserialize<'de>,
+        T: AbstractRadixTreeMut<K, V>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_tuple(3, NodeVisitor(false, PhantomData))
+        }
+    }
+
+    struct NodeVisitor<K, V, T>(bool, PhantomData<(K, V, T)>);
+
+    impl<'de, K, V, T> Visitor<'de> for NodeVisitor<K, V, T>
+    where
+        K: TKey + Deserialize<'de>,
+        V: TValue + Deserialize<'de>,
+        T: AbstractRadixTreeMut<K, V>,
+    {
+        type Value = NodeDe<K, V, T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("a radix tree node")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let SeqVec(prefix) = next::<_, SeqVec<K>>(&mut seq, 0, &self)?;
+            let value = next(&mut seq, 1, &self)?;
+            let SeqVec(children) = next::<_, SeqVec<NodeDe<K, V, T>>>(&mut seq, 2, &self)?;
+            let children = children.into_iter().map(|x| x.0).collect();
+            let node = T::new(prefix.as_slice().into(), value, children);
+            check_node(&node, self.0).map_err(de::Error::custom)?;
+            Ok(NodeDe(node, PhantomData))
+        }
+    }
+
+    /// Serialize a radix tree in structural form
+    pub fn serialize<K, V, T, S>(tree: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: TKey + Serialize,
+        V: TValue + Serialize,
+        T: AbstractRadixTree<K, V>,
+        S: Serializer,
+    {
+        Node(tree, PhantomData).serialize(serializer)
+    }
+
+    /// Deserialize a radix tree from structural form
+    pub fn deserialize<'de, K, V, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        K: TKey + Deserialize<'de>,
+        V: TValue + Deserialize<'de>,
+        T: AbstractRadixTreeMut<K, V>,
+        D: Deserializer<'de>,
+    {
+        let node = deserializer.deserialize_tuple(3, NodeVisitor(true, PhantomData))?;
+        Ok(node.0)
+    }
+}
+
+/// Serialize and deserialize a number of [ArcRadixTree]s as a DAG, preserving structural sharing.
+///
+/// Use this with `#[serde(with = "vec_collections::radix_tree::shared")]` on a `Vec<ArcRadixTree<K, V>>`,
+/// e.g. to store several snapshots of a tree without storing the parts they have in common multiple times.
+///
+/// The serialized form is a tuple `(lists, roots)`. `lists` contains every distinct children list of the trees
+/// exactly once, as a sequence of `(prefix, value, children)` nodes, where `children` is either none or the
+/// index of an earlier list. `roots` contains the root node of each tree in the same format.
+///
+/// Shared subtrees are shared again after deserialization. Deserialization fails if the nodes are not in
+/// canonical form.
+pub mod shared {
+    use super::super::{location, ArcRadixTree};
+    use super::*;
+    use std::{collections::BTreeMap, sync::Arc};
+
+    struct Node<'a, K, V> {
+        prefix: &'a [K],
+        value: Option<&'a V>,
+        children: Option<u64>,
+    }
+
+    impl<'a, K: Serialize, V: Serialize> Serialize for Node<'a, K, V> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut state = serializer.serialize_tuple(3)?;
+            state.serialize_element(&Seq(self.prefix))?;
+            state.serialize_element(&self.value)?;
+            state.serialize_element(&self.children)?;
+            state.end()
+        }
+    }
+
+    struct Lists<'a, K, V>(Vec<Vec<Node<'a, K, V>>>);
+
+    impl<'a, K: Serialize, V: Serialize> Serialize for Lists<'a, K, V> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(self.0.iter().map(|list| Seq(list)))
+        }
+    }
+
+    /// add the children of a tree to the lists, if they are not already there
+    fn intern<'a, K: TKey, V: TValue>(
+        tree: &'a ArcRadixTree<K, V>,
+        ids: &mut BTreeMap<usize, u64>,
+        lists: &mut Vec<Vec<Node<'a, K, V>>>,
+    ) -> Option<u64> {
+        let children = tree.children_arc();
+        if children.is_empty() {
+            return None;
+        }
+        let key = location(children.as_ref());
+        if let Some(id) = ids.get(&key) {
+            return Some(*id);
+        }
+        let list = children
+            .iter()
+            .map(|child| Node {
+                prefix: child.prefix(),
+                value: child.value(),
+                children: intern(child, ids, lists),
+            })
+            .collect();
+        let id = lists.len() as u64;
+        lists.push(list);
+        ids.insert(key, id);
+        Some(id)
+    }
+
+    /// Serialize a number of [ArcRadixTree]s as a DAG
+    pub fn serialize<K, V, S>(
+        trees: &[ArcRadixTree<K, V>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        K: TKey + Serialize,
+        V: TValue + Serialize,
+        S: Serializer,
+    {
+        let mut ids = BTreeMap::new();
+        let mut lists = Vec::new();
+        let roots = trees
+            .iter()
+            .map(|tree| Node {
+                prefix: tree.prefix(),
+                value: tree.value(),
+                children: intern(tree, &mut ids, &mut lists),
+            })
+            .collect::<Vec<_>>();
+        let mut state = serializer.serialize_tuple(2)?;
+        state.serialize_element(&Lists(lists))?;
+        state.serialize_element(&Seq(&roots))?;
+        state.end()
+    }
+
+    struct NodeDe<K, V> {
+        prefix: Vec<K>,
+        value: Option<V>,
+        children: Option<u64>,
+    }
+
+    impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for NodeDe<K, V> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_tuple(3, NodeVisitor(PhantomData))
+        }
+    }
+
+    struct NodeVisitor<K, V>(PhantomData<(K, V)>);
+
+    impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for NodeVisitor<K, V> {
+        type Value = NodeDe<K, V>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("a radix tree node")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let SeqVec(prefix) = next(&mut seq, 0, &self)?;
+            let value = next(&mut seq, 1, &self)?;
+            let children = next(&mut seq, 2, &self)?;
+            Ok(NodeDe {
+                prefix,
+                value,
+                children,
+            })
+        }
+    }
+
+    struct DagVisitor<K, V>(PhantomData<(K, V)>);
+
+    impl<'de, K, V> Visitor<'de> for DagVisitor<K, V>
+    where
+        K: TKey + Deserialize<'de>,
+        V: TValue + Deserialize<'de>,
+    {
+        type Value = Vec<ArcRadixTree<K, V>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("a radix tree dag")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let SeqVec(lists) = next::<_, SeqVec<SeqVec<NodeDe<K, V>>>>(&mut seq, 0, &self)?;
+            let SeqVec(roots) = next::<_, SeqVec<NodeDe<K, V>>>(&mut seq, 1, &self)?;
+            let mut arcs: Vec<Arc<Vec<ArcRadixTree<K, V>>>> = Vec::with_capacity(lists.len());
+            // a node may only refer to lists before the one it is in, so there can be no cycles
+            let build = |node: NodeDe<K, V>, arcs: &[Arc<Vec<ArcRadixTree<K, V>>>], root| {
+                let children = match node.children {
+                    Some(id) => arcs
+                        .get(id as usize)
+                        .cloned()
+                        .ok_or("invalid children reference")?,
+                    None => ArcRadixTree::default().children_arc().clone(),
+                };
+                let node =
+                    ArcRadixTree::from_arc(node.prefix.as_slice().into(), node.value, children);
+                check_node(&node, root)?;
+                Ok(node)
+            };
+            for SeqVec(list) in lists {
+                if list.is_empty() {
+                    return Err(de::Error::custom("empty children list"));
+                }
+                let list = list
+                    .into_iter()
+                    .map(|node| build(node, &arcs, false))
+                    .collect::<Result<Vec<_>, &'static str>>()
+                    .map_err(de::Error::custom)?;
+                arcs.push(Arc::new(list));
+            }
+            roots
+                .into_iter()
+                .map(|root| build(root, &arcs, true))
+                .collect::<Result<Vec<_>, &'static str>>()
+                .map_err(de::Error::custom)
+        }
+    }
+
+    /// Deserialize a number of [ArcRadixTree]s from a DAG
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Vec<ArcRadixTree<K, V>>, D::Error>
+    where
+        K: TKey + Deserialize<'de>,
+        V: TValue + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(2, DagVisitor(PhantomData))
+    }
+}
+
+macro_rules! flat_serde {
+    ($tree:ident, $($lt:lifetime,)? $tkey:path, $tvalue:path) => {
+        impl<$($lt,)? K: $tkey + Serialize, V: $tvalue + Serialize> Serialize for $tree<$($lt,)? K, V> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serialize_flat(self, serializer)
+            }
+        }
+
+        impl<'de, $($lt,)? K, V> Deserialize<'de> for $tree<$($lt,)? K, V>
+        where
+            K: $tkey + Deserialize<'de>,
+            V: $tvalue + Deserialize<'de>,
+        {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserialize_flat(deserializer)
+            }
+        }
+    };
+}
+
+use super::RadixTree;
+flat_serde!(RadixTree, TKey, TValue);
+
+use super::ArcRadixTree;
+flat_serde!(ArcRadixTree, TKey, TValue);
+
+#[cfg(feature = "lazy_radixtree")]
+use super::{lazy_radix_tree, LazyRadixTree};
+#[cfg(feature = "lazy_radixtree")]
+flat_serde!(LazyRadixTree, 'a, lazy_radix_tree::TKey, lazy_radix_tree::TValue);
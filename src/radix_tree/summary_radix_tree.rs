@@ -0,0 +1,338 @@
+use super::internals;
+use internals::{AbstractRadixTreeMut as _, Fragment};
+
+use super::{common_prefix, find, AbstractRadixTree, FindResult, IterKey, RadixTree, TKey, TValue};
+use std::{fmt::Debug, marker::PhantomData, ops::Add, sync::OnceLock};
+
+/// A monoid to summarize the values of a radix tree
+///
+/// `combine` must be associative, and `empty` must be its neutral element.
+pub trait Monoid<V> {
+    /// The summary of a number of values
+    type Summary: Debug + Clone + Send + Sync + 'static;
+
+    /// The summary of no values at all
+    fn empty() -> Self::Summary;
+
+    /// The summary of a single value
+    fn single(value: &V) -> Self::Summary;
+
+    /// Combine two summaries, where all values in `a` come before all values in `b`
+    fn combine(a: &Self::Summary, b: &Self::Summary) -> Self::Summary;
+}
+
+/// A monoid that can tell the number of values from a summary
+///
+/// This is needed for [nth](SummaryRadixTree::nth) and [rank](SummaryRadixTree::rank).
+pub trait CountingMonoid<V>: Monoid<V> {
+    /// The number of values summarized by `summary`
+    fn count(summary: &Self::Summary) -> usize;
+}
+
+/// Counts the values
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Count;
+
+impl<V> Monoid<V> for Count {
+    type Summary = usize;
+
+    fn empty() -> usize {
+        0
+    }
+
+    fn single(_: &V) -> usize {
+        1
+    }
+
+    fn combine(a: &usize, b: &usize) -> usize {
+        a + b
+    }
+}
+
+impl<V> CountingMonoid<V> for Count {
+    fn count(summary: &usize) -> usize {
+        *summary
+    }
+}
+
+/// Sums up the values, starting from the default value
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sum;
+
+impl<V: Debug + Clone + Default + Add<Output = V> + Send + Sync + 'static> Monoid<V> for Sum {
+    type Summary = V;
+
+    fn empty() -> V {
+        V::default()
+    }
+
+    fn single(value: &V) -> V {
+        value.clone()
+    }
+
+    fn combine(a: &V, b: &V) -> V {
+        a.clone() + b.clone()
+    }
+}
+
+/// The maximum of the values, or None for no values
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Max;
+
+impl<V: Debug + Clone + Ord + Send + Sync + 'static> Monoid<V> for Max {
+    type Summary = Option<V>;
+
+    fn empty() -> Option<V> {
+        None
+    }
+
+    fn single(value: &V) -> Option<V> {
+        Some(value.clone())
+    }
+
+    fn combine(a: &Option<V>, b: &Option<V>) -> Option<V> {
+        a.as_ref().max(b.as_ref()).cloned()
+    }
+}
+
+/// The minimum of the values, or None for no values
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Min;
+
+impl<V: Debug + Clone + Ord + Send + Sync + 'static> Monoid<V> for Min {
+    type Summary = Option<V>;
+
+    fn empty() -> Option<V> {
+        None
+    }
+
+    fn single(value: &V) -> Option<V> {
+        Some(value.clone())
+    }
+
+    fn combine(a: &Option<V>, b: &Option<V>) -> Option<V> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b).clone()),
+            (Some(x), None) | (None, Some(x)) => Some(x.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Two summaries at once, e.g. `(Count, Sum)`
+impl<V, A: Monoid<V>, B: Monoid<V>> Monoid<V> for (A, B) {
+    type Summary = (A::Summary, B::Summary);
+
+    fn empty() -> Self::Summary {
+        (A::empty(), B::empty())
+    }
+
+    fn single(value: &V) -> Self::Summary {
+        (A::single(value), B::single(value))
+    }
+
+    fn combine(a: &Self::Summary, b: &Self::Summary) -> Self::Summary {
+        (A::combine(&a.0, &b.0), B::combine(&a.1, &b.1))
+    }
+}
+
+impl<V, A: CountingMonoid<V>, B: Monoid<V>> CountingMonoid<V> for (A, B) {
+    fn count(summary: &Self::Summary) -> usize {
+        A::count(&summary.0)
+    }
+}
+
+/// A generic radix tree with a cached summary of the values of each subtree
+///
+/// The summary is computed using the monoid `M`. It is computed lazily when needed, and dropped for all
+/// nodes on the path to a change, so after a combine operation only the changed parts have to be summarized again.
+pub struct SummaryRadixTree<K, V, M: Monoid<V>> {
+    prefix: Fragment<K>,
+    value: Option<V>,
+    children: Vec<Self>,
+    summary: OnceLock<M::Summary>,
+    _m: PhantomData<M>,
+}
+
+impl<K: Clone, V: Clone, M: Monoid<V>> Clone for SummaryRadixTree<K, V, M> {
+    fn clone(&self) -> Self {
+        Self {
+            prefix: self.prefix.clone(),
+            value: self.value.clone(),
+            children: self.children.clone(),
+            summary: self.summary.clone(),
+            _m: PhantomData,
+        }
+    }
+}
+
+impl<K: Clone, V, M: Monoid<V>> Default for SummaryRadixTree<K, V, M> {
+    fn default() -> Self {
+        Self {
+            prefix: Fragment::default(),
+            value: None,
+            children: Vec::new(),
+            summary: OnceLock::new(),
+            _m: PhantomData,
+        }
+    }
+}
+
+impl<K: TKey, V: TValue, M: Monoid<V>> AbstractRadixTree<K, V> for SummaryRadixTree<K, V, M> {
+    type Materialized = SummaryRadixTree<K, V, M>;
+
+    fn prefix(&self) -> &[K] {
+        &self.prefix
+    }
+
+    fn value(&self) -> Option<&V> {
+        self.value.as_ref()
+    }
+
+    fn children(&self) -> &[Self] {
+        &self.children
+    }
+}
+
+impl<K: TKey, V: TValue, M: Monoid<V>> internals::AbstractRadixTreeMut<K, V>
+    for SummaryRadixTree<K, V, M>
+{
+    fn new(prefix: Fragment<K>, value: Option<V>, children: Vec<Self>) -> Self {
+        Self {
+            prefix,
+            value,
+            children,
+            summary: OnceLock::new(),
+            _m: PhantomData,
+        }
+    }
+
+    // every mutation of a subtree has to go through one of the accessors below for all nodes on the path,
+    // so dropping the summary there is enough to keep all summaries up to date.
+
+    fn value_mut(&mut self) -> &mut Option<V> {
+        self.summary.take();
+        &mut self.value
+    }
+
+    fn children_mut(&mut self) -> &mut [Self] {
+        self.summary.take();
+        &mut self.children
+    }
+
+    fn edit_children<R>(&mut self, f: impl FnOnce(&mut Vec<Self>) -> R) -> R {
+        self.summary.take();
+        f(&mut self.children)
+    }
+
+    fn set_prefix(&mut self, prefix: Fragment<K>) {
+        self.prefix = prefix;
+    }
+
+    fn value_and_children_mut(&mut self) -> (&mut Option<V>, &mut [Self]) {
+        self.summary.take();
+        (&mut self.value, &mut self.children)
+    }
+}
+
+impl_std_traits!(mut [K: TKey, V: TValue, M: Monoid<V>] SummaryRadixTree<K, V, M>);
+
+impl<K: TKey, V: TValue, M: Monoid<V>> From<RadixTree<K, V>> for SummaryRadixTree<K, V, M> {
+    fn from(value: RadixTree<K, V>) -> Self {
+        let RadixTree {
+            prefix,
+            value,
+            children,
+        } = value;
+        let children = children.into_iter().map(Self::from).collect::<Vec<_>>();
+        Self::new(prefix, value, children)
+    }
+}
+
+impl<K: TKey, V: TValue, M: Monoid<V>> SummaryRadixTree<K, V, M> {
+    /// The summary of all values in the tree
+    pub fn summary(&self) -> &M::Summary {
+        self.summary.get_or_init(|| {
+            let own = self.value.as_ref().map(M::single).unwrap_or_else(M::empty);
+            self.children
+                .iter()
+                .fold(own, |acc, child| M::combine(&acc, child.summary()))
+        })
+    }
+
+    /// The summary of all values whose key starts with the given prefix
+    pub fn summary_prefix(&self, prefix: &[K]) -> M::Summary {
+        match find(self, prefix) {
+            FindResult::Found(tree) | FindResult::Prefix { tree, .. } => tree.summary().clone(),
+            FindResult::NotFound { .. } => M::empty(),
+        }
+    }
+}
+
+impl<K: TKey, V: TValue, M: CountingMonoid<V>> SummaryRadixTree<K, V, M> {
+    /// The number of mappings in the tree
+    pub fn count(&self) -> usize {
+        M::count(self.summary())
+    }
+
+    /// The mapping at position `index` in key order, if any
+    pub fn nth(&self, mut index: usize) -> Option<(IterKey<K>, &V)> {
+        let mut key = IterKey::new(self.prefix());
+        let mut tree = self;
+        'outer: loop {
+            if let Some(value) = tree.value() {
+                if index == 0 {
+                    return Some((key, value));
+                }
+                index -= 1;
+            }
+            for child in tree.children() {
+                let n = M::count(child.summary());
+                if index < n {
+                    key.append(child.prefix());
+                    tree = child;
+                    continue 'outer;
+                }
+                index -= n;
+            }
+            return None;
+        }
+    }
+
+    /// The number of mappings with a key that is smaller than the given key
+    ///
+    /// This is the position the key has or would have in key order.
+    pub fn rank(&self, key: &[K]) -> usize {
+        let mut rank = 0;
+        let mut key = key;
+        let mut tree = self;
+        loop {
+            let prefix = tree.prefix();
+            let n = common_prefix(prefix, key);
+            if n < prefix.len() {
+                // all keys in the tree are either smaller or larger
+                if n < key.len() && prefix[n] < key[n] {
+                    rank += M::count(tree.summary());
+                }
+                return rank;
+            }
+            key = &key[n..];
+            if key.is_empty() {
+                return rank;
+            }
+            if tree.value().is_some() {
+                rank += 1;
+            }
+            let children = tree.children();
+            let index = children.partition_point(|child| child.prefix()[0] < key[0]);
+            rank += children[..index]
+                .iter()
+                .map(|child| M::count(child.summary()))
+                .sum::<usize>();
+            match children.get(index) {
+                Some(child) if child.prefix()[0] == key[0] => tree = child,
+                _ => return rank,
+            }
+        }
+    }
+}
@@ -364,7 +364,7 @@ mod tests {
         let mut samples: BTreeSet<i64> = BTreeSet::new();
         samples.extend(a.elements.as_ref().iter().cloned());
         samples.extend(b.elements.as_ref().iter().cloned());
-        samples.insert(core::i64::MIN);
+        samples.insert(i64::MIN);
         samples.iter().all(|e| {
             let expected = op(a.contains(e), b.contains(e));
             let actual = r.contains(e);
@@ -382,7 +382,7 @@ mod tests {
         let mut samples: BTreeSet<i64> = BTreeSet::new();
         samples.extend(a.elements.as_ref().iter().cloned());
         samples.extend(b.elements.as_ref().iter().cloned());
-        samples.insert(core::i64::MIN);
+        samples.insert(i64::MIN);
         if r {
             samples.iter().all(|e| {
                 let expected = op(a.contains(e), b.contains(e));
@@ -4,14 +4,18 @@ use crate::iterators::SliceIterator;
 use crate::{
     dedup::{sort_dedup_by_key, Keep},
     merge_state::{InPlaceSmallVecMergeStateRef, MergeStateMut, NoConverter, SmallVecMergeState},
+    vec_set::range_indices,
     VecSet,
 };
 use crate::{iterators::VecMapIter, merge_state::InPlaceMergeState};
 use binary_merge::MergeOperation;
 #[cfg(feature = "rkyv_validated")]
 use bytecheck::CheckBytes;
-use core::{borrow::Borrow, cmp::Ordering, fmt, fmt::Debug, hash, hash::Hash, iter::FromIterator};
-#[cfg(feature = "rkyv")]
+use core::{
+    borrow::Borrow, cmp::Ordering, fmt, fmt::Debug, hash, hash::Hash, iter::FromIterator,
+    ops::RangeBounds,
+};
+#[cfg(feature = "rkyv_validated")]
 use rkyv::{validation::ArchiveContext, Archive};
 use smallvec::{Array, SmallVec};
 use std::collections::BTreeMap;
@@ -34,7 +38,7 @@ pub trait AbstractVecMap<K, V> {
         self.as_slice().is_empty()
     }
 
-    fn iter(&self) -> VecMapIter<core::slice::Iter<(K, V)>> {
+    fn iter(&self) -> VecMapIter<core::slice::Iter<'_, (K, V)>> {
         VecMapIter::new(self.as_slice().iter())
     }
 
@@ -51,6 +55,87 @@ pub trait AbstractVecMap<K, V> {
             .ok()
     }
 
+    /// An iterator over the mappings of this map whose keys are within the given range, in key order.
+    ///
+    /// Time complexity is O(log N) to find the start and the end of the range. Binary search.
+    fn range<Q, R>(&self, range: R) -> VecMapIter<core::slice::Iter<'_, (K, V)>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let elements = self.as_slice();
+        let range = range_indices(elements, |(k, _)| k.borrow(), &range);
+        VecMapIter::new(elements[range].iter())
+    }
+
+    /// The mapping with the smallest key, if any.
+    fn first(&self) -> Option<&(K, V)> {
+        self.as_slice().first()
+    }
+
+    /// The mapping with the largest key, if any.
+    fn last(&self) -> Option<&(K, V)> {
+        self.as_slice().last()
+    }
+
+    /// The number of mappings with a key that is strictly smaller than `key`.
+    ///
+    /// Time complexity is O(log N). Binary search.
+    fn rank<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.as_slice().partition_point(|(k, _)| k.borrow() < key)
+    }
+
+    /// The mapping at position `index` in key order, so `select(0)` is the mapping with the smallest key.
+    ///
+    /// Time complexity is O(1).
+    fn select(&self, index: usize) -> Option<&(K, V)> {
+        self.as_slice().get(index)
+    }
+
+    /// The mapping with the smallest key that is greater than or equal to `key`.
+    fn lower_bound<Q>(&self, key: &Q) -> Option<&(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.select(self.rank(key))
+    }
+
+    /// The mapping with the smallest key that is strictly greater than `key`.
+    fn upper_bound<Q>(&self, key: &Q) -> Option<&(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let elements = self.as_slice();
+        elements.get(elements.partition_point(|(k, _)| k.borrow() <= key))
+    }
+
+    /// The mapping with the largest key that is strictly smaller than `key`.
+    fn predecessor<Q>(&self, key: &Q) -> Option<&(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.rank(key)
+            .checked_sub(1)
+            .and_then(|index| self.select(index))
+    }
+
+    /// The mapping with the smallest key that is strictly greater than `key`. Same as [upper_bound](AbstractVecMap::upper_bound).
+    fn successor<Q>(&self, key: &Q) -> Option<&(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.upper_bound(key)
+    }
+
     /// Perform an outer join with another VecMap, producing a new result
     ///
     ///
@@ -201,6 +286,7 @@ impl<A: Array> From<VecMap<A>> for VecSet<A> {
     }
 }
 
+#[allow(dead_code)]
 struct CombineOp<F, K>(F, std::marker::PhantomData<K>);
 
 impl<'a, K: Ord, V, A: Array<Item = (K, V)>, B: Array<Item = (K, V)>, F: Fn(V, V) -> V>
@@ -640,7 +726,7 @@ impl<A: Array> VecMap<A> {
     }
 
     #[cfg(feature = "total")]
-    pub(crate) fn slice_iter(&self) -> SliceIterator<A::Item> {
+    pub(crate) fn slice_iter(&self) -> SliceIterator<'_, A::Item> {
         SliceIterator(self.0.as_slice())
     }
 
@@ -652,6 +738,22 @@ impl<A: Array> VecMap<A> {
     pub fn single(item: A::Item) -> Self {
         Self(smallvec::smallvec![item])
     }
+
+    /// Removes and returns the mapping with the smallest key, if any.
+    ///
+    /// The time complexity of this is O(N), since all remaining elements have to be moved.
+    pub fn pop_first(&mut self) -> Option<A::Item> {
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(self.0.remove(0))
+        }
+    }
+
+    /// Removes and returns the mapping with the largest key, if any.
+    pub fn pop_last(&mut self) -> Option<A::Item> {
+        self.0.pop()
+    }
 }
 
 impl<K: Ord + 'static, V, A: Array<Item = (K, V)>> VecMap<A> {
@@ -761,6 +863,258 @@ impl<K: Ord + 'static, V, A: Array<Item = (K, V)>> VecMap<A> {
             Err(_) => None,
         }
     }
+
+    /// An iterator over the mappings of this map whose keys are within the given range, in key order,
+    /// with mutable references to the values.
+    #[allow(clippy::type_complexity)]
+    pub fn range_mut<Q, R>(
+        &mut self,
+        range: R,
+    ) -> VecMapIter<
+        core::iter::Map<core::slice::IterMut<'_, (K, V)>, fn(&mut (K, V)) -> (&K, &mut V)>,
+    >
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let range = range_indices(&self.0, |(k, _)| k.borrow(), &range);
+        let split: fn(&mut (K, V)) -> (&K, &mut V) = |(k, v)| (&*k, v);
+        VecMapIter::new(self.0[range].iter_mut().map(split))
+    }
+
+    /// Splits the map in two at the given key. Returns everything after the given key,
+    /// including the key itself.
+    pub fn split_off<Q>(&mut self, at: &Q) -> Self
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let index = self.0.partition_point(|(k, _)| k.borrow() < at);
+        Self(self.0.drain(index..).collect())
+    }
+
+    /// Removes all mappings whose keys are within the given range from the map, and returns them as an iterator.
+    ///
+    /// The mappings are removed even if the iterator is not fully consumed.
+    pub fn drain_range<Q, R>(&mut self, range: R) -> VecMapIter<smallvec::Drain<'_, A>>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let range = range_indices(&self.0, |(k, _)| k.borrow(), &range);
+        VecMapIter::new(self.0.drain(range))
+    }
+
+    /// Removes a key from the map, returning the value at the key if the key was previously in the map.
+    ///
+    /// The time complexity of this is O(N), so removing many keys using single key removes will be slow!
+    /// Prefer using [retain](VecMap::retain) when removing a large number of keys.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Removes a key from the map, returning the stored key and value if the key was previously in the map.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        match self.0.binary_search_by(|p| p.0.borrow().cmp(key)) {
+            Ok(index) => Some(self.0.remove(index)),
+            Err(_) => None,
+        }
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    ///
+    /// The binary search is done only once, so e.g. `map.entry(k).or_default()` is cheaper
+    /// than a `get_mut` followed by an `insert`.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V, A> {
+        match self.0.binary_search_by(|(k, _)| k.cmp(&key)) {
+            Ok(index) => Entry::Occupied(OccupiedEntry { map: self, index }),
+            Err(index) => Entry::Vacant(VacantEntry {
+                map: self,
+                key,
+                index,
+            }),
+        }
+    }
+}
+
+/// A view into a single entry in a [VecMap], which may either be vacant or occupied.
+///
+/// This is constructed from the [entry](VecMap::entry) method on [VecMap].
+pub enum Entry<'a, K, V, A: Array<Item = (K, V)>> {
+    /// A vacant entry
+    Vacant(VacantEntry<'a, K, V, A>),
+    /// An occupied entry
+    Occupied(OccupiedEntry<'a, K, V, A>),
+}
+
+/// A view into a vacant entry in a [VecMap]. It is part of the [Entry] enum.
+pub struct VacantEntry<'a, K, V, A: Array<Item = (K, V)>> {
+    map: &'a mut VecMap<A>,
+    key: K,
+    // insertion point, as found by the binary search
+    index: usize,
+}
+
+/// A view into an occupied entry in a [VecMap]. It is part of the [Entry] enum.
+pub struct OccupiedEntry<'a, K, V, A: Array<Item = (K, V)>> {
+    map: &'a mut VecMap<A>,
+    // position of the entry, as found by the binary search
+    index: usize,
+}
+
+impl<'a, K: Debug, V: Debug, A: Array<Item = (K, V)>> Debug for Entry<'a, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Entry::Vacant(e) => f.debug_tuple("Entry").field(e).finish(),
+            Entry::Occupied(e) => f.debug_tuple("Entry").field(e).finish(),
+        }
+    }
+}
+
+impl<'a, K: Debug, V, A: Array<Item = (K, V)>> Debug for VacantEntry<'a, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("VacantEntry").field(self.key()).finish()
+    }
+}
+
+impl<'a, K: Debug, V: Debug, A: Array<Item = (K, V)>> Debug for OccupiedEntry<'a, K, V, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OccupiedEntry")
+            .field("key", self.key())
+            .field("value", self.get())
+            .finish()
+    }
+}
+
+impl<'a, K: 'a, V: 'a, A: Array<Item = (K, V)>> Entry<'a, K, V, A> {
+    /// Returns a reference to this entry's key.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Vacant(e) => e.key(),
+            Entry::Occupied(e) => e.key(),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the default if empty, and returns
+    /// a mutable reference to the value in the entry.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Vacant(e) => e.insert(default),
+            Entry::Occupied(e) => e.into_mut(),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting the result of the default function if empty,
+    /// and returns a mutable reference to the value in the entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(e) => e.insert(default()),
+            Entry::Occupied(e) => e.into_mut(),
+        }
+    }
+
+    /// Ensures a value is in the entry by inserting, if empty, the result of the default function,
+    /// which gets a reference to the key.
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Vacant(e) => {
+                let value = default(e.key());
+                e.insert(value)
+            }
+            Entry::Occupied(e) => e.into_mut(),
+        }
+    }
+
+    /// Provides in-place mutable access to an occupied entry before any potential inserts into the map.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            Entry::Occupied(mut e) => {
+                f(e.get_mut());
+                Entry::Occupied(e)
+            }
+            Entry::Vacant(e) => Entry::Vacant(e),
+        }
+    }
+}
+
+impl<'a, K: 'a, V: Default + 'a, A: Array<Item = (K, V)>> Entry<'a, K, V, A> {
+    /// Ensures a value is in the entry by inserting the default value if empty,
+    /// and returns a mutable reference to the value in the entry.
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K: 'a, V: 'a, A: Array<Item = (K, V)>> VacantEntry<'a, K, V, A> {
+    /// Gets a reference to the key that would be used when inserting a value through the VacantEntry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Take ownership of the key.
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    /// Sets the value of the entry with the VacantEntry's key, and returns a mutable reference to it.
+    ///
+    /// The time complexity of this is O(N), since all elements after the insertion point have to be moved.
+    pub fn insert(self, value: V) -> &'a mut V {
+        let VacantEntry { map, key, index } = self;
+        map.0.insert(index, (key, value));
+        &mut map.0[index].1
+    }
+}
+
+impl<'a, K: 'a, V: 'a, A: Array<Item = (K, V)>> OccupiedEntry<'a, K, V, A> {
+    /// Gets a reference to the key in the entry.
+    pub fn key(&self) -> &K {
+        &self.map.0[self.index].0
+    }
+
+    /// Gets a reference to the value in the entry.
+    pub fn get(&self) -> &V {
+        &self.map.0[self.index].1
+    }
+
+    /// Gets a mutable reference to the value in the entry.
+    ///
+    /// If you need a reference that outlives the entry, use [into_mut](OccupiedEntry::into_mut).
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.0[self.index].1
+    }
+
+    /// Converts the entry into a mutable reference to its value, with the lifetime of the map.
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.0[self.index].1
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Takes the value of the entry out of the map, and returns it.
+    ///
+    /// The time complexity of this is O(N), since all elements after the entry have to be moved.
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    /// Take ownership of the key and value from the map.
+    pub fn remove_entry(self) -> (K, V) {
+        self.map.0.remove(self.index)
+    }
 }
 
 #[cfg(feature = "serde")]
@@ -1009,6 +1363,144 @@ mod tests {
             let actual = a.inner_join(&b, |_, a,_| Some(*a));
             expected == actual
         }
+
+        fn remove(a: Ref, keys: Vec<i32>) -> bool {
+            let mut expected = a.clone();
+            let mut actual: Test = a.into();
+            keys.iter().all(|k| expected.remove(k) == actual.remove(k))
+                && Test::from(expected) == actual
+        }
+
+        fn pop_first_last(a: Ref) -> bool {
+            let mut expected = a.clone();
+            let mut actual: Test = a.into();
+            while !expected.is_empty() {
+                let first = expected.keys().next().cloned().unwrap();
+                if actual.pop_first() != expected.remove_entry(&first) {
+                    return false;
+                }
+                if let Some(last) = expected.keys().next_back().cloned() {
+                    if actual.pop_last() != expected.remove_entry(&last) {
+                        return false;
+                    }
+                }
+            }
+            actual.is_empty()
+        }
+
+        fn range(a: Ref, x: i32, y: i32) -> bool {
+            let (x, y) = if x <= y { (x, y) } else { (y, x) };
+            let a1: Test = a.clone().into();
+            let expected: Vec<(i32, i32)> = a.range(x..=y).map(|(k, v)| (*k, *v)).collect();
+            let actual: Vec<(i32, i32)> = a1.range(x..=y).cloned().collect();
+            expected == actual
+        }
+
+        fn range_mut(a: Ref, x: i32, y: i32) -> bool {
+            let (x, y) = if x <= y { (x, y) } else { (y, x) };
+            let mut expected = a.clone();
+            let mut actual: Test = a.into();
+            for (_, v) in expected.range_mut(x..y) {
+                *v = v.wrapping_add(1);
+            }
+            for (_, v) in actual.range_mut(x..y) {
+                *v = v.wrapping_add(1);
+            }
+            Test::from(expected) == actual
+        }
+
+        fn order_statistics(a: Ref, x: i32) -> bool {
+            let a1: Test = a.clone().into();
+            let rank = a.range(..x).count();
+            let pair = |e: Option<(&i32, &i32)>| e.map(|(k, v)| (*k, *v));
+            let pair1 = |e: Option<&(i32, i32)>| e.cloned();
+            pair1(a1.first()) == pair(a.iter().next())
+                && pair1(a1.last()) == pair(a.iter().next_back())
+                && a1.rank(&x) == rank
+                && pair1(a1.select(rank)) == pair(a.range(x..).next())
+                && pair1(a1.lower_bound(&x)) == pair(a.range(x..).next())
+                && pair1(a1.upper_bound(&x)) == pair(a.range(x..).find(|(k, _)| **k > x))
+                && pair1(a1.predecessor(&x)) == pair(a.range(..x).next_back())
+                && pair1(a1.successor(&x)) == pair(a.range(x..).find(|(k, _)| **k > x))
+        }
+
+        fn split_off(a: Ref, x: i32) -> bool {
+            let mut a = a;
+            let mut a1: Test = a.clone().into();
+            let b = a.split_off(&x);
+            let b1 = a1.split_off(&x);
+            Test::from(a) == a1 && Test::from(b) == b1
+        }
+
+        fn drain_range(a: Ref, x: i32, y: i32) -> bool {
+            let (x, y) = if x <= y { (x, y) } else { (y, x) };
+            let mut a1: Test = a.clone().into();
+            let drained: Vec<(i32, i32)> = a1.drain_range(x..y).collect();
+            let expected: Vec<(i32, i32)> = a.range(x..y).map(|(k, v)| (*k, *v)).collect();
+            let mut remaining = a;
+            remaining.retain(|k, _| !(x..y).contains(k));
+            drained == expected && Test::from(remaining) == a1
+        }
+
+        fn entry_count(keys: Vec<i32>) -> bool {
+            let mut expected = Ref::new();
+            let mut actual = Test::default();
+            for k in keys {
+                *expected.entry(k).or_default() += 1;
+                *actual.entry(k).or_default() += 1;
+            }
+            let expected: Test = expected.into();
+            expected == actual
+        }
+
+        fn entry_remove(a: Ref, keys: Vec<i32>) -> bool {
+            let mut expected = a.clone();
+            let mut actual: Test = a.into();
+            keys.into_iter().all(|k| {
+                let e = expected.remove(&k);
+                let a = match actual.entry(k) {
+                    Entry::Occupied(e) => Some(e.remove()),
+                    Entry::Vacant(_) => None,
+                };
+                e == a
+            }) && Test::from(expected) == actual
+        }
+    }
+
+    #[test]
+    fn remove_smoke() {
+        let mut map: VecMap1<String, i32> =
+            btreemap! { "a".to_owned() => 1, "b".to_owned() => 2, "c".to_owned() => 3 }.into();
+        assert_eq!(map.remove("b"), Some(2));
+        assert_eq!(map.remove("b"), None);
+        assert_eq!(map.remove_entry("a"), Some(("a".to_owned(), 1)));
+        assert_eq!(map.pop_first(), Some(("c".to_owned(), 3)));
+        assert_eq!(map.pop_first(), None);
+        assert_eq!(map.pop_last(), None);
+    }
+
+    #[test]
+    fn entry_smoke() {
+        let mut map: Test = btreemap! { 1 => 1, 3 => 3 }.into();
+        assert_eq!(*map.entry(2).or_insert(2), 2);
+        assert_eq!(*map.entry(2).or_insert(5), 2);
+        assert_eq!(*map.entry(4).or_insert_with_key(|k| k * 10), 40);
+        assert_eq!(*map.entry(1).and_modify(|v| *v += 10).or_insert(0), 11);
+        assert_eq!(*map.entry(5).and_modify(|v| *v += 10).or_insert(0), 0);
+        match map.entry(3) {
+            Entry::Occupied(mut e) => {
+                assert_eq!(e.key(), &3);
+                assert_eq!(e.insert(30), 3);
+                assert_eq!(e.remove_entry(), (3, 30));
+            }
+            Entry::Vacant(_) => panic!(),
+        }
+        match map.entry(3) {
+            Entry::Vacant(e) => assert_eq!(e.into_key(), 3),
+            Entry::Occupied(_) => panic!(),
+        }
+        let expected: Test = btreemap! { 1 => 11, 2 => 2, 4 => 40, 5 => 0 }.into();
+        assert_eq!(map, expected);
     }
 
     #[test]
@@ -11,13 +11,17 @@ use binary_merge::MergeOperation;
 #[cfg(feature = "rkyv_validated")]
 use bytecheck::CheckBytes;
 use core::{
+    borrow::Borrow,
     cmp::Ordering,
     fmt, hash,
     hash::Hash,
     iter::FromIterator,
-    ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Sub, SubAssign},
+    ops::{
+        BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Bound, Range, RangeBounds,
+        Sub, SubAssign,
+    },
 };
-#[cfg(feature = "rkyv")]
+#[cfg(feature = "rkyv_validated")]
 use rkyv::{validation::ArchiveContext, Archive};
 use smallvec::{Array, SmallVec};
 use std::collections::BTreeSet;
@@ -35,6 +39,32 @@ struct SetIntersectionOp;
 struct SetXorOp;
 struct SetDiffOpt;
 
+/// The index range of the elements of a sorted slice that are within the given bounds.
+///
+/// `key` extracts the part of an element that the bounds refer to. If the start bound is after the
+/// end bound, the result is an empty range.
+pub(crate) fn range_indices<T, Q, R>(
+    elements: &[T],
+    key: impl Fn(&T) -> &Q,
+    range: &R,
+) -> Range<usize>
+where
+    Q: Ord + ?Sized,
+    R: RangeBounds<Q>,
+{
+    let start = match range.start_bound() {
+        Bound::Included(b) => elements.partition_point(|x| key(x) < b),
+        Bound::Excluded(b) => elements.partition_point(|x| key(x) <= b),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(b) => elements.partition_point(|x| key(x) <= b),
+        Bound::Excluded(b) => elements.partition_point(|x| key(x) < b),
+        Bound::Unbounded => elements.len(),
+    };
+    start..end.max(start)
+}
+
 /// A set backed by a [SmallVec] of elements.
 ///
 /// `A` the underlying storage. This must be an array. The size of this array is the maximum size this collection
@@ -191,9 +221,90 @@ pub trait AbstractVecSet<T: Ord> {
     }
 
     /// An iterator that returns references to the items of this set in sorted order
-    fn iter(&self) -> VecSetIter<core::slice::Iter<T>> {
+    fn iter(&self) -> VecSetIter<core::slice::Iter<'_, T>> {
         VecSetIter::new(self.as_slice().iter())
     }
+
+    /// An iterator over the elements of this set that are within the given range, in sorted order.
+    ///
+    /// Time complexity is O(log N) to find the start and the end of the range. Binary search.
+    fn range<Q, R>(&self, range: R) -> VecSetIter<core::slice::Iter<'_, T>>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let elements = self.as_slice();
+        let range = range_indices(elements, |x| x.borrow(), &range);
+        VecSetIter::new(elements[range].iter())
+    }
+
+    /// The smallest element of the set, if any.
+    fn first(&self) -> Option<&T> {
+        self.as_slice().first()
+    }
+
+    /// The largest element of the set, if any.
+    fn last(&self) -> Option<&T> {
+        self.as_slice().last()
+    }
+
+    /// The number of elements in the set that are strictly smaller than `value`.
+    ///
+    /// Time complexity is O(log N). Binary search.
+    fn rank<Q>(&self, value: &Q) -> usize
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.as_slice().partition_point(|x| x.borrow() < value)
+    }
+
+    /// The element at position `index` in sorted order, so `select(0)` is the smallest element.
+    ///
+    /// Time complexity is O(1).
+    fn select(&self, index: usize) -> Option<&T> {
+        self.as_slice().get(index)
+    }
+
+    /// The smallest element that is greater than or equal to `value`.
+    fn lower_bound<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.select(self.rank(value))
+    }
+
+    /// The smallest element that is strictly greater than `value`.
+    fn upper_bound<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let elements = self.as_slice();
+        elements.get(elements.partition_point(|x| x.borrow() <= value))
+    }
+
+    /// The largest element that is strictly smaller than `value`.
+    fn predecessor<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.rank(value)
+            .checked_sub(1)
+            .and_then(|index| self.select(index))
+    }
+
+    /// The smallest element that is strictly greater than `value`. Same as [upper_bound](AbstractVecSet::upper_bound).
+    fn successor<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.upper_bound(value)
+    }
 }
 
 impl<A: Array> AbstractVecSet<A::Item> for VecSet<A>
@@ -269,7 +380,7 @@ impl<A: Array> VecSet<A> {
         Self::new_unsafe(SmallVec::new())
     }
     /// An iterator that returns references to the items of this set in sorted order
-    pub fn iter(&self) -> VecSetIter<core::slice::Iter<A::Item>> {
+    pub fn iter(&self) -> VecSetIter<core::slice::Iter<'_, A::Item>> {
         VecSetIter::new(self.0.iter())
     }
     /// The underlying memory as a slice.
@@ -333,6 +444,30 @@ where
         self.0.retain(|entry| f(entry))
     }
 
+    /// Splits the set in two at the given element. Returns everything after the given element,
+    /// including the element itself.
+    pub fn split_off<Q>(&mut self, at: &Q) -> Self
+    where
+        A::Item: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        let index = self.0.partition_point(|x| x.borrow() < at);
+        Self::new_unsafe(self.0.drain(index..).collect())
+    }
+
+    /// Removes all elements within the given range from the set, and returns them as an iterator.
+    ///
+    /// The elements are removed even if the iterator is not fully consumed.
+    pub fn drain_range<Q, R>(&mut self, range: R) -> VecSetIter<smallvec::Drain<'_, A>>
+    where
+        A::Item: Borrow<Q>,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        let range = range_indices(&self.0, |x| x.borrow(), &range);
+        VecSetIter::new(self.0.drain(range))
+    }
+
     /// creates a set from a vec.
     ///
     /// Will sort and deduplicate the vector using a stable merge sort, so worst case time complexity
@@ -941,6 +1076,53 @@ mod test {
             expected == actual
         }
 
+        fn range(a: Reference, x: i64, y: i64) -> bool {
+            let (x, y) = if x <= y { (x, y) } else { (y, x) };
+            let a1: Test = a.iter().cloned().collect();
+            fn check<'a>(
+                expected: impl Iterator<Item = &'a i64>,
+                actual: impl Iterator<Item = &'a i64>,
+            ) -> bool {
+                expected.eq(actual)
+            }
+            check(a.range(x..y), a1.range(x..y))
+                && check(a.range(x..=y), a1.range(x..=y))
+                && check(a.range(..y), a1.range(..y))
+                && check(a.range(x..), a1.range(x..))
+                && check(a.range(..), a1.range(..))
+                && check(a.range(x..y).rev(), a1.range(x..y).rev())
+        }
+
+        fn order_statistics(a: Reference, x: i64) -> bool {
+            let a1: Test = a.iter().cloned().collect();
+            let rank = a.range(..x).count();
+            a1.first() == a.iter().next()
+                && a1.last() == a.iter().next_back()
+                && a1.rank(&x) == rank
+                && a1.select(rank) == a.range(x..).next()
+                && a1.lower_bound(&x) == a.range(x..).next()
+                && a1.upper_bound(&x) == a.range(x..).find(|e| **e > x)
+                && a1.predecessor(&x) == a.range(..x).next_back()
+                && a1.successor(&x) == a.range(x..).find(|e| **e > x)
+        }
+
+        fn split_off(a: Reference, x: i64) -> bool {
+            let mut a = a;
+            let mut a1: Test = a.iter().cloned().collect();
+            let b = a.split_off(&x);
+            let b1 = a1.split_off(&x);
+            a.into_iter().eq(a1) && b.into_iter().eq(b1)
+        }
+
+        fn drain_range(a: Reference, x: i64, y: i64) -> bool {
+            let (x, y) = if x <= y { (x, y) } else { (y, x) };
+            let mut a1: Test = a.iter().cloned().collect();
+            let drained: Vec<i64> = a1.drain_range(x..y).collect();
+            let expected: Vec<i64> = a.range(x..y).cloned().collect();
+            let remaining: Vec<i64> = a.iter().filter(|e| !(x..y).contains(e)).cloned().collect();
+            drained == expected && Vec::from(a1) == remaining
+        }
+
         fn contains(a: Reference, b: i64) -> bool {
             let a1: Test = a.iter().cloned().collect();
             let expected = a.contains(&b);